@@ -3,94 +3,198 @@
 ---@field os string
 ---@field hid string
 ---@field is_system boolean?
+---@field pm string? package manager for remove/query/search, detected from the facts by default
+---@field timeout string? ssh only, limit of a connection attempt
+---@field retries integer? ssh only, connection attempts made after the first one fails
+---@field backoff string? ssh only, delay before the first reconnect
 ---
----@class ExecOptions
+---@class RetryOptions defaults come from --timeout, --retries and --backoff, once, refresh and dot operations run a single attempt
+---@field timeout string? limit of a single attempt, e.g. "30s"
+---@field retries integer? attempts made after the first one fails
+---@field backoff string? delay before the first retry, doubled on each further one
+
+---overwrite, update, upload, download, delete_src, delete_dst; as "upload,update", {"upload", "update"},
+---{upload = true, update = true} or the legacy digits "123"
+---@alias Confirm string|string[]|table<string, boolean>
+
+---@class SyncOptions: RetryOptions
+---@field mode "one_way"|"bidirectional"? bidirectional propagates changes both ways since the last such sync, confirm is ignored
+---@field scan integer? path pairs scanned at once, defaults to --jobs
+---@field transfer integer? files transferred at once, defaults to --jobs
+
+---@class ExecOptions: RetryOptions
 ---@field reply boolean
 ---@field etor string?
 
+---@class Disk
+---@field total integer
+---@field used integer
+---@field available integer
+---
+---@class Facts
+---@field arch string normalized architecture, e.g. amd64, arm64
+---@field machine string raw `uname -m`
+---@field kernel string
+---@field system string
+---@field distro string?
+---@field version string?
+---@field hostname string
+---@field cpus integer?
+---@field memory integer?
+---@field disk Disk?
+---@field shell string?
+---@field home string?
+---@field pms string[]
+
 ---@class User
 ---@field exec fun(this: User, cmd: string, opt:boolean|ExecOptions?)
----@field read fun(this: User, path: string): string
----@field write fun(this: User, path: string, content: string)
+---@field read fun(this: User, path: string, opts: RetryOptions?): string
+---@field write fun(this: User, path: string, content: string, opts: RetryOptions?)
+---@field facts fun(this: User, refresh: boolean?): Facts unsupported on windows hosts
+---@field persist fun(this: User, ...: string) store the given vars for later runs
 ---@field user string
 ---@field os string
 ---@field [string] string
 ---
+---@class ContainerCfg
+---@field runtime "docker"|"podman"?
+---@field container string
+---@field user string?
+---
+---@class GroupResult
+---@field ok boolean
+---@field err string?
+---@field result boolean?
+---@field code integer?
+---@field stdout string?
+---@field stderr string?
+---@field packages table<string, PackageResult>? by install
+---
+---@class Group
+---@field members fun(this: Group): string[]
+---@field exec fun(this: Group, cmd: string, opt:boolean|ExecOptions?): table<string, GroupResult>
+---@field write fun(this: Group, path: string, content: string, opts: RetryOptions?): table<string, GroupResult>
+---@field sync fun(this: Group, src: string, src_path: string, dst_path: string, confirm: Confirm?, opts: SyncOptions?): table<string, GroupResult>
+---@field install fun(this: Group, apps: Packages, opts: boolean|PmOptions?): table<string, GroupResult>
+
+---Users declared in the inventory (`--inventory`) are added on first access
 ---@class UM
----@field add_cur fun(this: UM, cfg: table)
----@field add_ssh fun(this: UM, uid: string, cfg: table)
+---@field add_cur fun(this: UM, cfg: UserCfg|table)
+---@field add_ssh fun(this: UM, uid: string, cfg: UserCfg|table)
+---@field add_container fun(this: UM, uid: string, cfg: ContainerCfg|table)
+---@field add_local_as fun(this: UM, uid: string, cfg: {user: string}|table) another local account, through sudo
+---@field add_chroot fun(this: UM, uid: string, cfg: {root: string, nspawn: boolean?}|table) a directory root, through chroot or systemd-nspawn
+---@field import_ssh_config fun(this: UM, path: string?, pattern: string?): string[] declare the hosts of an OpenSSH config as ssh users
+---@field add_group fun(this: UM, name: string, uids: string[])
+---@field group fun(this: UM, name: string, opt: {policy: "fail_fast"|"continue"}?): Group
 ---@field [string] User
 
+---Only for cur and ssh users, sync container, local_as and chroot users with dv:sync
 ---@class Dot
----@field confirm fun(this: Dot, default: string)
+---@field confirm fun(this: Dot, default: Confirm?)
 ---@field add_schema fun(this: Dot, name: string, path: string)
 ---@field add_source fun(this: Dot, name: string, path: string)
 ---@field sync fun(this: Dot, apps: table, uid: string)
 ---@field upload fun(this: Dot, apps: table, uid: string)
 
----@class Pm
----@field install fun(this: Pm, hid: string, apps: string, confirm: boolean)
----@field update fun(this: Pm, hid: string, confirm: boolean)
----@field upgrade fun(this: Pm, hid: string, apps: string, confirm: boolean)
+---a name, or a name pinned to a version (unsupported by pacman, its helpers, brew and winget),
+---matching installed versions it is a whole prefix of, e.g. 14.1 matches 14.1.1 but not 14.10.0
+---@alias Package string|{name: string, version: string?}
+---"fd ripgrep" or {"fd", {name = "ripgrep", version = "14.1"}}
+---@alias Packages string|Package[]
 
----@class Dv
----@field sync fun(this: Dv, src: string, src_paths: string|table, dest: string, dest_paths: string|table, confirm: string?)
----@field dl fun(this: Dv, url: string, expire?: string)
----@field um fun(this: Dv):UM
----@field dot fun(this: Dv):Dot
----@field pm fun(this: Dv):Pm
----@field json fun(this: Dv, text: string|any):table|string
-dv = dv
+---@class PackageResult
+---@field ok boolean whether the package ended up as asked, or the operation's result in a dry run
+---@field version string? installed version
 
-UTB = {}
+---@class PmOptions: RetryOptions
+---@field confirm boolean? show the transaction simulated by the package manager and ask before making it
+---@field backend "flatpak"|"snap"? instead of the system package manager, versions are flatpak branches or snap channels
+---@field scope "system"|"user"? flatpak installs for the whole system (default) or the first user of the device
+---@field remote string? flatpak remote to install from
+---@field classic boolean? install snaps with classic confinement
 
----@param name string
----@return UserCfg
-local function default_user_cfg(name)
-  return {
-    mount = "~/.local/share/dv",
-    os = "linux",
-    hid = name,
-  }
-end
+---@class EnsureSpec: PmOptions
+---@field present string[]?
+---@field absent string[]?
 
-local function init_user_table()
-  local base = {
-    mount = "~/.local/share/dv",
-  }
-  UTB["cur"] = base
+---A language-level manager installing for the user given, or the first user of the device, versions as the manager spells them
+---@class LangPm
+---@field install fun(this: LangPm, apps: Packages, opts: boolean|PmOptions?): table<string, PackageResult>
+---@field remove fun(this: LangPm, apps: Packages, opts: boolean|PmOptions?): table<string, PackageResult> go takes package paths
+---@field is_installed fun(this: LangPm, app: string, opts: PmOptions?): boolean
+---@field installed_version fun(this: LangPm, app: string, opts: PmOptions?): string?
+---@field list_installed fun(this: LangPm, opts: PmOptions?): table<string, string> versions by package
 
-  base.hid = "local"
-  UTB["system"] = base
+---@class RepoSpec: RetryOptions
+---@field name string file and section name
+---@field url string apt archive root, dnf baseurl or pacman server
+---@field key string? url of the signing key for apt and dnf, fingerprint to receive for pacman
+---@field suite string? apt suite, the release codename by default
+---@field components string? apt components, "main" by default
 
-  local rt = {
-    mount = base.mount,
-    os = "ubuntu",
-    hid = "rt",
-  }
-  UTB["rt"] = rt
+---A device by hid, using its system account or else its first user, or a user of it to run as
+---by uid or as a User; the user, given or picked, must be able to run the package manager, and be
+---root or allowed to sudo for snap and system-wide flatpak changes. Only declared users and hosts are connected.
+---Install, update and upgrade ask for the sudo password when needed, except as proxy users; the rest need sudo without one
+---Container, local_as and chroot users count as devices of their own
+---The package manager is the `pm` var of the user, else detected: apt to brew from facts, winget or scoop on windows
+---@alias PmTarget string|User
 
-  rt.is_system = false
-  UTB["rt-r"] = rt
-end
+---@class Pm
+---@field alias fun(this: Pm, name: string, names: table<string, string>) e.g. pm:alias("fd", {apt = "fd-find"}), other managers keep the name
+---@field load_aliases fun(this: Pm, path: string) toml tables of aliases by logical name, see packages.toml
+---@field install fun(this: Pm, who: PmTarget, apps: Packages, opts: boolean|PmOptions?): table<string, PackageResult>
+---@field update fun(this: Pm, who: PmTarget, opts: boolean|PmOptions?)
+---@field upgrade fun(this: Pm, who: PmTarget, apps: Packages, opts: boolean|PmOptions?): table<string, PackageResult>
+---@field remove fun(this: Pm, who: PmTarget, apps: Packages, opts: boolean|PmOptions?): table<string, PackageResult> ok once absent
+---@field ensure fun(this: Pm, who: PmTarget, spec: EnsureSpec): {installed: string[], removed: string[]} only changes what drifted, listing what did change
+---@field is_installed fun(this: Pm, who: PmTarget, app: string, opts: PmOptions?): boolean
+---@field installed_version fun(this: Pm, who: PmTarget, app: string, opts: PmOptions?): string?
+---@field list_installed fun(this: Pm, who: PmTarget, opts: PmOptions?): table<string, string> versions by package
+---@field add_repo fun(this: Pm, who: PmTarget, spec: RepoSpec): boolean whether anything changed, run update afterwards
+---@field remove_repo fun(this: Pm, who: PmTarget, name: string, opts: RetryOptions?): boolean removes its key too
+---@field add_flatpak_remote fun(this: Pm, who: PmTarget, name: string, url: string, opts: PmOptions?) kept if it exists
+---@field remove_flatpak_remote fun(this: Pm, who: PmTarget, name: string, opts: PmOptions?)
+---@field flatpak_remotes fun(this: Pm, who: PmTarget, opts: PmOptions?): table<string, string> urls by name
+---@field search fun(this: Pm, who: PmTarget, term: string, opts: PmOptions?): {name: string, description: string}[]
+---@field cargo fun(this: Pm, who: PmTarget): LangPm
+---@field pip fun(this: Pm, who: PmTarget): LangPm `pip install --user`, failing on an externally managed python (PEP 668) where pipx is the way
+---@field pipx fun(this: Pm, who: PmTarget): LangPm
+---@field npm fun(this: Pm, who: PmTarget): LangPm `npm install -g` into the configured prefix if writable, else ~/.local
+---@field go fun(this: Pm, who: PmTarget): LangPm package paths, installed `@latest` unless pinned
 
-init_user_table()
+-- a mismatch fails dv:dl and discards the download
+---@class DlOptions: RetryOptions
+---@field sha256 string? hex digest
+---@field sha512 string? hex digest
+---@field minisign string? public key, base64 or the content of its .pub file
+---@field gpg string? keyring checked with gpgv, exclusive with minisign as both read the one signature file
+---@field signature string? url of the detached signature, <url>.minisig by default for minisign
 
-function Load_user(...)
-  local um = dv:um()
-  for _, uid in ipairs({ ... }) do
-    local cfg = UTB[uid] or default_user_cfg(uid)
-    if uid == "cur" then
-      um:add_cur(cfg)
-    else
-      um:add_ssh(uid, cfg)
-    end
-  end
-  return um
-end
+---@class Task
+---@field is_finished fun(this: Task): boolean
+---@field join fun(this: Task): any
+
+-- dv:sync src_paths may also hold pairs, as {{src, dst}, ...}, {{src = .., dst = ..}, ...} or {[src] = dst, ...}, with dest_paths nil
+-- a container, local_as or chroot side is staged in a local directory, which needs the cur user
+---@class Dv
+---@field sync fun(this: Dv, src: string, src_paths: string|string[]|table, dest: string, dest_paths: string|string[]|nil, confirm: Confirm?, opts: SyncOptions?): boolean, string[]? whether anything changed, and in bidirectional mode the files changed on both sides
+---@field dl fun(this: Dv, url: string, expire?: string, opts: DlOptions?): string local path
+---@field um fun(this: Dv):UM
+---@field dot fun(this: Dv):Dot
+---@field pm fun(this: Dv):Pm
+---@field json fun(this: Dv, text: string|any):table|string
+---@field spawn fun(this: Dv, f: function, ...: any): Task run f concurrently, a task never joined still fails the run if f errors
+---@field join fun(this: Dv, tasks: Task|Task[]): any values of a task, or the first value of each task
+---@field all fun(this: Dv, fs: function[]): any[] run every function concurrently, the first value of each
+dv = dv
 
 ---@param source string
 function Load_dot(source)
+  -- users of inventory.toml are added on first access, and dot needs them added
+  local um = dv:um()
+  assert(um.cur and um.rt, "cur and rt must be declared in the inventory")
   local schema = dv:dl("https://raw.githubusercontent.com/km0e/schema/main/dot.toml", "7days")
   local dot = dv:dot()
   dot:add_schema("cur", schema)
@@ -100,13 +204,13 @@ function Load_dot(source)
 end
 
 function Main()
-  local um = Load_user("cur", "system", "rt")
+  local um = dv:um()
   local dot = Load_dot("~/.local/share/dv/main")
   if um.cur.os == "windows" then
-    dot:confirm("uy")
+    dot:confirm("upload,update")
     dot:upload({ "alacritty", "git", "nvim" }, "cur")
   else
-    dot:confirm("yu")
+    dot:confirm("upload,update")
     dot:upload({
       "alacritty",
       "fish",
@@ -123,12 +227,13 @@ function Main()
 end
 
 local function latest_fish_tar()
-  local um = Load_user("cur")
+  local um = dv:um()
   local path = dv:dl("https://api.github.com/repos/fish-shell/fish-shell/releases/latest", "1days")
   local j = dv:json(um.cur:read(path))
+  local arch = um.cur:facts().arch
   local target = nil
   for _, asset in ipairs(j["assets"]) do
-    if asset["name"]:match("static%-amd64") then
+    if asset["name"]:match("static%-" .. arch) then
       target = asset
     end
   end
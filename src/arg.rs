@@ -1,5 +1,6 @@
 use clap::{Arg, Command};
-use std::path::PathBuf;
+use humantime_serde::re::humantime::parse_duration;
+use std::{path::PathBuf, time::Duration};
 
 pub struct Args {
     pub cache_dir: Option<PathBuf>,
@@ -7,51 +8,88 @@ pub struct Args {
     pub dbpath: PathBuf,
     pub dry_run: bool,
     pub entry: String,
+    pub inventory: Option<PathBuf>,
     pub rargs: Vec<String>,
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    pub backoff: Option<Duration>,
+    pub jobs: usize,
 }
 
 pub fn cli() -> Args {
-    let matches =
-        Command::new("dv4lua")
-            .version(env!("CARGO_PKG_VERSION"))
-            .about("Simple CLI to use dv-api with lua")
-            .arg(
-                Arg::new("dbpath")
-                    .short('b')
-                    .long("dbpath")
-                    .help("database path, default [$directory/.cache] -> [project cache dir]"),
-            )
-            .arg(Arg::new("cache_dir").short('a').long("cache-dir").help(
+    let matches = Command::new("dv4lua")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Simple CLI to use dv-api with lua")
+        .arg(
+            Arg::new("dbpath")
+                .short('b')
+                .long("dbpath")
+                .help("database path, default [$directory/.cache] -> [project cache dir]"),
+        )
+        .arg(
+            Arg::new("cache_dir").short('a').long("cache-dir").help(
                 "The cache directory to use, default [project cache dir] -> [$directory/cache]",
-            ))
-            .arg(Arg::new("config").short('c').long("config").help(
-                "The config file to use, default [$directory/config.lua] -> [project config dir]",
-            ))
-            .arg(
-                Arg::new("directory")
-                    .short('d')
-                    .long("directory")
-                    .help("The directory to use for the config and cache"),
-            )
-            .arg(
-                Arg::new("dry_run")
-                    .short('n')
-                    .long("dry-run")
-                    .action(clap::ArgAction::SetTrue)
-                    .default_value("false")
-                    .help("Do not actually modify anything"),
-            )
-            .arg(
-                Arg::new("entry")
-                    .help("The entry point of the script")
-                    .default_value("Main"),
-            )
-            .arg(
-                Arg::new("rargs")
-                    .num_args(0..)
-                    .help("Arguments to pass to the entry point"),
-            )
-            .get_matches();
+            ),
+        )
+        .arg(Arg::new("config").short('c').long("config").help(
+            "The config file to use, default [$directory/config.lua] -> [project config dir]",
+        ))
+        .arg(
+            Arg::new("directory")
+                .short('d')
+                .long("directory")
+                .help("The directory to use for the config and cache"),
+        )
+        .arg(Arg::new("inventory").short('i').long("inventory").help(
+            "The inventory file (toml/yaml) declaring users and groups, default [$directory/inventory.toml]",
+        ))
+        .arg(
+            Arg::new("dry_run")
+                .short('n')
+                .long("dry-run")
+                .action(clap::ArgAction::SetTrue)
+                .default_value("false")
+                .help("Do not actually modify anything"),
+        )
+        .arg(
+            Arg::new("timeout")
+                .short('t')
+                .long("timeout")
+                .value_parser(parse_duration)
+                .help("Default timeout of a single attempt of a remote operation (exec, read, write, sync, pm, dl), e.g. 30s, unlimited by default"),
+        )
+        .arg(
+            Arg::new("retries")
+                .short('r')
+                .long("retries")
+                .value_parser(clap::value_parser!(u32))
+                .help("Default number of retries of a failed remote operation, default 0"),
+        )
+        .arg(
+            Arg::new("backoff")
+                .long("backoff")
+                .value_parser(parse_duration)
+                .help("Default delay before the first retry, doubled on each further one, default 1s"),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("4")
+                .help("Default number of paths scanned and files transferred at once by sync"),
+        )
+        .arg(
+            Arg::new("entry")
+                .help("The entry point of the script")
+                .default_value("Main"),
+        )
+        .arg(
+            Arg::new("rargs")
+                .num_args(0..)
+                .help("Arguments to pass to the entry point"),
+        )
+        .get_matches();
 
     let directory = matches.get_one::<PathBuf>("directory");
     let cache_dir = matches
@@ -79,6 +117,14 @@ pub fn cli() -> Args {
                 .map(|d| d.config_local_dir().join("config.lua"))
         })
         .expect("config must be calculated");
+    let inventory = matches
+        .get_one::<PathBuf>("inventory")
+        .cloned()
+        .or_else(|| {
+            directory
+                .map(|d| d.join("inventory.toml"))
+                .filter(|p| p.exists())
+        });
     let dry_run = matches
         .get_one::<bool>("dry_run")
         .expect("defaulted by clap");
@@ -91,12 +137,21 @@ pub fn cli() -> Args {
         .unwrap_or_default()
         .cloned()
         .collect();
+    let timeout = matches.get_one::<Duration>("timeout").copied();
+    let retries = matches.get_one::<u32>("retries").copied();
+    let backoff = matches.get_one::<Duration>("backoff").copied();
+    let jobs = *matches.get_one::<usize>("jobs").expect("defaulted by clap");
     Args {
         dbpath,
         cache_dir,
         config,
         dry_run: *dry_run,
         entry,
+        inventory,
         rargs,
+        timeout,
+        retries,
+        backoff,
+        jobs,
     }
 }
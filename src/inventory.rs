@@ -0,0 +1,183 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Result, bail};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserKind {
+    Cur,
+    #[default]
+    Ssh,
+    Container,
+    LocalAs,
+    Chroot,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserSpec {
+    pub kind: UserKind,
+    pub is_system: Option<bool>,
+    pub vars: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+enum Var {
+    String(String),
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+}
+
+impl From<Var> for String {
+    fn from(value: Var) -> Self {
+        match value {
+            Var::String(s) => s,
+            Var::Bool(b) => b.to_string(),
+            Var::Integer(i) => i.to_string(),
+            Var::Float(f) => f.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct RawUser {
+    #[serde(default)]
+    kind: UserKind,
+    is_system: Option<bool>,
+    #[serde(flatten)]
+    vars: HashMap<String, Var>,
+}
+
+/// A user's vars are layered as global `vars` < `hosts.<hid>` < the user's own.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inventory {
+    #[serde(default)]
+    vars: HashMap<String, Var>,
+    #[serde(default)]
+    hosts: HashMap<String, HashMap<String, Var>>,
+    #[serde(default)]
+    users: HashMap<String, RawUser>,
+    #[serde(default)]
+    groups: HashMap<String, Vec<String>>,
+}
+
+impl Inventory {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Ok(toml::from_str(&content)?),
+            Some("yaml" | "yml") => Ok(serde_yaml::from_str(&content)?),
+            _ => bail!(
+                "Unsupported inventory format: {}, expected .toml, .yaml or .yml",
+                path.display()
+            ),
+        }
+    }
+    pub fn user(&self, uid: &str) -> Option<UserSpec> {
+        let raw = self.users.get(uid)?;
+        let mut vars: HashMap<String, String> = self
+            .vars
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone().into()))
+            .collect();
+        let hid = match raw.vars.get("hid") {
+            Some(hid) => hid.clone().into(),
+            None => uid.to_string(),
+        };
+        if let Some(host) = self.hosts.get(&hid) {
+            vars.extend(host.iter().map(|(k, v)| (k.clone(), v.clone().into())));
+        }
+        vars.extend(raw.vars.iter().map(|(k, v)| (k.clone(), v.clone().into())));
+        Some(UserSpec {
+            kind: raw.kind,
+            is_system: raw.is_system,
+            vars,
+        })
+    }
+    pub fn users_of(&self, hid: &str) -> Vec<String> {
+        let mut uids: Vec<String> = self
+            .users
+            .iter()
+            .filter(|(uid, raw)| match raw.vars.get("hid") {
+                Some(h) => String::from(h.clone()) == hid,
+                None => uid.as_str() == hid,
+            })
+            .map(|(uid, _)| uid.clone())
+            .collect();
+        uids.sort();
+        uids
+    }
+    pub fn group(&self, name: &str) -> Option<&[String]> {
+        self.groups.get(name).map(Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inventory, UserKind};
+
+    #[test]
+    fn inventory_layering() {
+        let inv: Inventory = toml::from_str(
+            r#"
+            [vars]
+            mount = "~/.local/share/dv"
+            os = "linux"
+
+            [hosts.rt]
+            os = "ubuntu"
+
+            [users.rt]
+            port = 2222
+
+            [users.rt-r]
+            hid = "rt"
+            is_system = false
+            mount = "/opt/dv"
+
+            [users.dev]
+            kind = "container"
+            container = "devbox"
+
+            [groups]
+            servers = ["rt", "rt-r"]
+            "#,
+        )
+        .expect("Failed to deserialize");
+
+        let rt = inv.user("rt").expect("rt declared");
+        assert_eq!(rt.kind, UserKind::Ssh);
+        assert_eq!(rt.vars["os"], "ubuntu");
+        assert_eq!(rt.vars["port"], "2222");
+        assert_eq!(rt.vars["mount"], "~/.local/share/dv");
+
+        let rt_r = inv.user("rt-r").expect("rt-r declared");
+        assert_eq!(rt_r.is_system, Some(false));
+        assert_eq!(rt_r.vars["os"], "ubuntu");
+        assert_eq!(rt_r.vars["mount"], "/opt/dv");
+
+        let dev = inv.user("dev").expect("dev declared");
+        assert_eq!(dev.kind, UserKind::Container);
+        assert_eq!(dev.vars["os"], "linux");
+
+        assert!(inv.user("missing").is_none());
+        assert_eq!(inv.users_of("rt"), vec!["rt", "rt-r"]);
+        assert!(inv.users_of("missing").is_empty());
+        assert_eq!(
+            inv.group("servers"),
+            Some(&["rt".to_string(), "rt-r".to_string()][..])
+        );
+    }
+
+    #[test]
+    fn inventory_yaml() {
+        let inv: Inventory =
+            serde_yaml::from_str("users:\n  cur:\n    kind: cur\n    mount: ~/.local/share/dv\n")
+                .expect("Failed to deserialize");
+        let cur = inv.user("cur").expect("cur declared");
+        assert_eq!(cur.kind, UserKind::Cur);
+        assert_eq!(cur.vars["mount"], "~/.local/share/dv");
+    }
+}
@@ -1,4 +1,3 @@
-#![allow(clippy::await_holding_refcell_ref)]
 static DIR: std::sync::LazyLock<Option<directories::ProjectDirs>> =
     std::sync::LazyLock::new(|| directories::ProjectDirs::from("dev", "dv", "dv4lua"));
 
@@ -6,7 +5,10 @@ use dv_wrap::{Context, MultiDB, TermInteractor};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod arg;
+mod inventory;
 mod multi;
+mod ssh_config;
+mod state;
 mod util;
 
 fn lua_string_escape(s: &str) -> String {
@@ -37,17 +39,45 @@ async fn main() -> Result<(), mlua::Error> {
         dry_run,
         entry,
         dbpath,
+        inventory,
         rargs,
+        timeout,
+        retries,
+        backoff,
+        jobs,
     } = arg::cli();
 
-    tracing::debug!(?config, ?cache_dir, ?dry_run, ?entry, ?dbpath, ?rargs);
+    tracing::debug!(
+        ?config,
+        ?cache_dir,
+        ?dry_run,
+        ?entry,
+        ?dbpath,
+        ?inventory,
+        ?rargs,
+        ?timeout,
+        ?retries,
+        ?backoff,
+        ?jobs
+    );
 
+    let inventory = inventory
+        .map(|path| inventory::Inventory::load(&path))
+        .transpose()
+        .map_err(mlua::Error::external)?
+        .unwrap_or_default();
+    let state = state::State::open(&dbpath).map_err(mlua::Error::external)?;
     let mut cache = MultiDB::default();
     cache.add_sqlite(dbpath).map_err(mlua::Error::external)?;
     let interactor = TermInteractor::new().map_err(mlua::Error::external)?;
     let ctx = Context::new(cache, cache_dir, interactor);
 
-    let ctx = multi::register(ctx, dry_run)?;
+    let retry = multi::RetryOptions {
+        timeout,
+        retries,
+        backoff,
+    };
+    let ctx = multi::register(ctx, state, inventory, retry, jobs, dry_run)?;
 
     let mut content = std::fs::read_to_string(&config).unwrap_or_else(|_| {
         tracing::error!("Failed to read config file: {}", config.display());
@@ -66,6 +96,12 @@ async fn main() -> Result<(), mlua::Error> {
     tracing::info!("Executing entry point: {}", call.trim());
     content.push_str(&call);
 
-    ctx.lua().load(content).exec_async().await?;
+    // lua functions passed to `dv:spawn` run on this set, unjoined ones are awaited before exiting
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(ctx.lua().load(content).exec_async())
+        .await?;
+    local.run_until(ctx.finish()).await?;
+    local.await;
     Ok(())
 }
@@ -6,76 +6,300 @@ mod dev {
 use anyhow::bail;
 use dev::*;
 
+use dv_api::process::ScriptExecutor;
 use dv_wrap::ops::{self, SyncEntry, SyncOpt};
 use futures::{StreamExt, TryStreamExt, stream};
-use std::{cell::RefCell, rc::Rc, time::Duration};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+
+use shared::{Lease, Shared, SharedRef, SharedRefMut};
 
 use dv_wrap::Context;
-use mlua::{FromLua, Function, Lua, LuaSerdeExt, Value};
+use mlua::{FromLua, Function, IntoLuaMulti, Lua, LuaSerdeExt, MultiValue, Value};
 
-use crate::util::{conversion_error, sync_opts};
+use crate::inventory::{Inventory, UserSpec};
+use crate::state::State;
+use crate::util::{Confirm, conversion_error};
+pub use retry::RetryOptions;
+use verify::DlOptions;
 
+mod bisync;
 mod dot;
+mod facts;
+mod group;
 mod pm;
+mod proxy;
+mod retry;
+mod shared;
+mod task;
 mod user;
+mod verify;
 
 #[derive(Clone)]
 pub struct ContextWrapper {
-    ctx: Rc<RefCell<Context>>,
+    ctx: Rc<Shared<Context>>,
     lua: Rc<RefCell<Lua>>,
+    state: Rc<RefCell<State>>,
+    inventory: Rc<Inventory>,
+    vars: Rc<RefCell<HashMap<String, HashMap<String, String>>>>,
+    proxies: Rc<RefCell<HashMap<String, Rc<proxy::ProxyUser>>>>,
+    declared: Rc<RefCell<HashMap<String, UserSpec>>>,
+    groups: Rc<RefCell<HashMap<String, Vec<String>>>>,
+    aliases: Rc<RefCell<HashMap<String, HashMap<String, String>>>>,
+    /// tasks from `dv:spawn`, so the ones never joined can be reported before exiting
+    tasks: Rc<RefCell<Vec<task::Task>>>,
+    /// held from showing a preview until its question is answered, so prompts of concurrent
+    /// operations don't interleave
+    prompt: Rc<tokio::sync::Mutex<()>>,
+    retry: RetryOptions,
+    jobs: usize,
     dry_run: bool,
 }
 
-impl dv_wrap::AsRefContext for ContextWrapper {
+/// dv-wrap reads the context synchronously, so it gets it through a lease, which keeps it
+/// from being borrowed exclusively
+impl dv_wrap::AsRefContext for Lease<Context> {
     fn as_ref(&self) -> impl std::ops::Deref<Target = Context> + '_ {
-        self.ctx()
+        self.get()
     }
 }
 
 impl ContextWrapper {
-    fn new(ctx: dv_wrap::Context, dry_run: bool) -> Self {
+    fn new(
+        ctx: dv_wrap::Context,
+        state: State,
+        inventory: Inventory,
+        retry: RetryOptions,
+        jobs: usize,
+        dry_run: bool,
+    ) -> Self {
         Self {
-            ctx: Rc::new(RefCell::new(ctx)),
+            ctx: Rc::new(Shared::new(ctx)),
             lua: Rc::new(RefCell::new(Lua::new())),
+            state: Rc::new(RefCell::new(state)),
+            inventory: Rc::new(inventory),
+            vars: Rc::default(),
+            proxies: Rc::default(),
+            declared: Rc::default(),
+            groups: Rc::default(),
+            aliases: Rc::default(),
+            tasks: Rc::default(),
+            prompt: Rc::default(),
+            retry,
+            jobs,
             dry_run,
         }
     }
-    fn ctx(&self) -> std::cell::Ref<'_, Context> {
-        self.ctx.borrow()
+    async fn ctx(&self) -> SharedRef<'_, Context> {
+        self.ctx.borrow().await
     }
-    fn ctx_mut(&self) -> std::cell::RefMut<'_, Context> {
-        self.ctx.borrow_mut()
+    async fn ctx_mut(&self) -> SharedRefMut<'_, Context> {
+        self.ctx.borrow_mut().await
+    }
+    async fn lease(&self) -> Lease<Context> {
+        self.ctx.lease().await
     }
     pub fn lua(&self) -> std::cell::Ref<'_, Lua> {
         self.lua.borrow()
     }
+    pub async fn finish(&self) -> mlua::Result<()> {
+        let tasks = std::mem::take(&mut *self.tasks.borrow_mut());
+        let mut failed = 0;
+        for task in tasks.iter().filter(|t| t.is_pending()) {
+            if let Err(e) = task.join().await {
+                tracing::error!("Spawned task failed: {e}");
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(mlua::Error::external(anyhow::anyhow!(
+                "{failed} spawned task(s) failed without being joined"
+            )));
+        }
+        Ok(())
+    }
+    fn state(&self) -> std::cell::Ref<'_, State> {
+        self.state.borrow()
+    }
+    fn state_mut(&self) -> std::cell::RefMut<'_, State> {
+        self.state.borrow_mut()
+    }
+    fn proxy(&self, uid: &str) -> Option<Rc<proxy::ProxyUser>> {
+        self.proxies.borrow().get(uid).cloned()
+    }
+    /// the var `key` of `uid`, vars set during this run shadow the ones it was added with
+    async fn user_var(&self, uid: &str, key: &str) -> Option<String> {
+        if let Some(value) = self.vars.borrow().get(uid).and_then(|v| v.get(key)) {
+            return Some(value.clone());
+        }
+        if let Some(proxy) = self.proxy(uid) {
+            return proxy.vars.get(key).cloned();
+        }
+        self.ctx().await.get_user(uid)?.vars.get(key).cloned()
+    }
+    async fn hid(&self, uid: &str) -> Option<String> {
+        if self.proxy(uid).is_some() {
+            return None;
+        }
+        self.ctx().await.get_user(uid)?.vars.get("hid").cloned()
+    }
+    /// whether `uid` is added, users that are only declared don't count
+    async fn contains_user(&self, uid: &str) -> bool {
+        let is_proxy = self.proxies.borrow().contains_key(uid);
+        is_proxy || self.ctx().await.contains_user(uid)
+    }
+    async fn exec(
+        &self,
+        uid: &str,
+        commands: &str,
+        reply: bool,
+        etor: Option<ScriptExecutor>,
+        retry: &RetryOptions,
+    ) -> Result<proxy::Output> {
+        self.retry(&format!("exec on {uid}"), retry, || async {
+            if let Some(proxy) = self.proxy(uid) {
+                return proxy.exec(commands, reply, etor.clone()).await;
+            }
+            let output = ops::exec(&*self.ctx().await, uid, commands, reply, etor.clone()).await?;
+            let (stdout, stderr): (&[u8], &[u8]) = (output.stdout.as_ref(), output.stderr.as_ref());
+            Ok(proxy::Output {
+                code: output.code,
+                stdout: stdout.to_vec(),
+                stderr: stderr.to_vec(),
+            })
+        })
+        .await
+    }
+    async fn read(&self, uid: &str, path: &str, retry: &RetryOptions) -> Result<String> {
+        self.retry(&format!("read on {uid}"), retry, || async {
+            if let Some(proxy) = self.proxy(uid) {
+                return proxy.read(path).await;
+            }
+            ops::read(&*self.ctx().await, uid, path).await
+        })
+        .await
+    }
+    async fn write(
+        &self,
+        uid: &str,
+        path: &str,
+        content: &str,
+        retry: &RetryOptions,
+    ) -> Result<bool> {
+        self.retry(&format!("write on {uid}"), retry, || async {
+            if let Some(proxy) = self.proxy(uid) {
+                return proxy.write(path, content).await;
+            }
+            ops::write(&*self.ctx().await, uid, path, content).await
+        })
+        .await
+    }
+    /// syncs through copies of the proxy sides in a local staging directory, with `cur`
+    /// standing in for them so `confirm` applies as for any other sync
+    async fn sync_proxy(
+        &self,
+        src: &str,
+        dst: &str,
+        pairs: &[(String, String)],
+        opts: &[SyncOpt],
+        sync_opt: &SyncOptions,
+    ) -> Result<bool> {
+        if !user::ensure_user(self, "cur").await? {
+            bail!("Sync with a proxy user stages files through cur, add it first: {src} -> {dst}");
+        }
+        let staging = proxy::Staging::new()?;
+        // each side as synced, its proxy and whether the sync may change it
+        let src_changes = opts.contains(&SyncOpt::DOWNLOAD) || opts.contains(&SyncOpt::DELETESRC);
+        let sides = [
+            (src, self.proxy(src), src_changes),
+            (dst, self.proxy(dst), true),
+        ];
+        let mut staged = pairs.to_vec();
+        for (i, pair) in staged.iter_mut().enumerate() {
+            for (side, (uid, proxy, _)) in sides.iter().enumerate() {
+                let Some(proxy) = proxy else {
+                    continue;
+                };
+                let local = staging.path(i, side);
+                let path = if side == 0 { &pair.0 } else { &pair.1 };
+                if proxy.exists(path).await? {
+                    self.retry(&format!("copy from {uid}"), &sync_opt.retry, || {
+                        proxy.copy_out(path, &local)
+                    })
+                    .await?;
+                }
+                *(if side == 0 { &mut pair.0 } else { &mut pair.1 }) = local;
+            }
+        }
+        let [staged_src, staged_dst] = sides.each_ref().map(|(uid, proxy, _)| match proxy {
+            Some(_) => "cur",
+            None => *uid,
+        });
+        let changed = Box::pin(self.sync(staged_src, staged_dst, &staged, opts, sync_opt)).await?;
+        if !changed || self.dry_run {
+            return Ok(changed);
+        }
+        for (side, (uid, proxy, changes)) in sides.iter().enumerate() {
+            let (Some(proxy), true) = (proxy, *changes) else {
+                continue;
+            };
+            for (pair, staged) in pairs.iter().zip(&staged) {
+                let (path, local) = if side == 0 {
+                    (&pair.0, &staged.0)
+                } else {
+                    (&pair.1, &staged.1)
+                };
+                // the staged copy is the whole new state, deletions included
+                self.retry(&format!("copy to {uid}"), &sync_opt.retry, || async {
+                    proxy.remove(path).await?;
+                    if tokio::fs::try_exists(local).await? {
+                        proxy.copy_in(local, path).await?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                })
+                .await?;
+            }
+        }
+        Ok(changed)
+    }
     async fn sync(
         &self,
         src: impl AsRef<str>,
         dst: impl AsRef<str>,
         pairs: &[(String, String)],
-        confirm: Option<&str>,
+        opts: &[SyncOpt],
+        sync_opt: &SyncOptions,
     ) -> Result<bool> {
-        let opts = sync_opts(confirm.unwrap_or_default())?;
-        let ctx = self.ctx();
-        let sync_ctx = ops::SyncContext::new(&ctx, src.as_ref(), dst.as_ref(), &opts);
-        let res = stream::iter(pairs)
-            .map(|(src_path, dst_path)| sync_ctx.scan(src_path, dst_path))
-            .buffered(4)
-            .try_fold(Vec::new(), |mut res, copy_res| async move {
-                res.extend(copy_res);
-                Ok(res)
+        if self.proxy(src.as_ref()).is_some() || self.proxy(dst.as_ref()).is_some() {
+            return self
+                .sync_proxy(src.as_ref(), dst.as_ref(), pairs, opts, sync_opt)
+                .await;
+        }
+        let what = format!("sync {} -> {}", src.as_ref(), dst.as_ref());
+        let scan = sync_opt.scan.unwrap_or(self.jobs).max(1);
+        let res = self
+            .retry(&what, &sync_opt.retry, || async {
+                let ctx = self.ctx().await;
+                let sync_ctx = ops::SyncContext::new(&ctx, src.as_ref(), dst.as_ref(), opts);
+                stream::iter(pairs)
+                    .map(|(src_path, dst_path)| sync_ctx.scan(src_path, dst_path))
+                    .buffered(scan)
+                    .try_fold(Vec::new(), |mut res, copy_res| async move {
+                        res.extend(copy_res);
+                        Ok(res)
+                    })
+                    .await
             })
             .await?;
-        self.sync_impl(src, dst, &res).await
+        self.sync_impl(src, dst, &res, sync_opt).await
     }
     async fn sync_impl(
         &self,
         src: impl AsRef<str>,
         dst: impl AsRef<str>,
         entries: &[SyncEntry],
+        sync_opt: &SyncOptions,
     ) -> Result<bool> {
-        let ctx = self.ctx();
+        let ctx = self.ctx().await;
         for e in entries {
             match e.opt {
                 SyncOpt::OVERWRITE => {
@@ -114,8 +338,23 @@ impl ContextWrapper {
         if self.dry_run {
             return Ok(true);
         }
+        let transfer = sync_opt.transfer.unwrap_or(self.jobs).max(1);
         let sync_ctx = ops::SyncContext::new(&ctx, src.as_ref(), dst.as_ref(), &[]);
-        sync_ctx.execute(entries).await
+        // entries are independent files, so each one is a separate transfer
+        stream::iter(entries)
+            .map(|e| {
+                let what = format!("sync {} -> {}", e.src, e.dst);
+                let sync_ctx = &sync_ctx;
+                async move {
+                    self.retry(&what, &sync_opt.retry, || {
+                        sync_ctx.execute(std::slice::from_ref(e))
+                    })
+                    .await
+                }
+            })
+            .buffer_unordered(transfer)
+            .try_fold(false, |res, done| async move { Ok(res | done) })
+            .await
     }
     async fn once(
         &self,
@@ -123,32 +362,36 @@ impl ContextWrapper {
         key: impl AsRef<str>,
         f: Function,
     ) -> Result<bool, mlua::Error> {
-        let once = ops::Once::new(self.clone(), id.as_ref(), key.as_ref());
+        let once = ops::Once::new(self.lease().await, id.as_ref(), key.as_ref());
         if !once.test().await? {
             return Ok(false);
         }
         self.ctx()
+            .await
             .interactor
             .log(format!("Once executing: {}:{}", id.as_ref(), key.as_ref()))
             .await;
         if self.dry_run {
             return Ok(true);
         }
+        // the callback may add users, which needs the context exclusively
+        drop(once);
         let res = f.call_async::<bool>(()).await;
         if res.is_ok() {
-            once.execute().await?;
+            ops::Once::new(self.lease().await, id.as_ref(), key.as_ref())
+                .execute()
+                .await?;
         }
         res
     }
     async fn refresh(&self, id: impl AsRef<str>, key: impl AsRef<str>) -> Result<()> {
-        self.ctx()
-            .interactor
+        let ctx = self.ctx().await;
+        ctx.interactor
             .log(format!("Refresh: {}:{}", id.as_ref(), key.as_ref()))
             .await;
         if self.dry_run {
             return Ok(());
         }
-        let ctx = self.ctx();
         ops::refresh(&ctx, id.as_ref(), key.as_ref()).await
     }
 
@@ -156,28 +399,94 @@ impl ContextWrapper {
         &self,
         url: impl AsRef<str>,
         expire: Option<humantime_serde::Serde<Duration>>,
+        opts: &DlOptions,
     ) -> Result<String, mlua::Error> {
+        let url = url.as_ref();
         let expire = expire.map(|e| e.as_secs());
-        let (path, dl) = ops::Dl::new(self.clone(), url.as_ref(), expire).await?;
-        let Some(dl) = dl else {
+        let path = self.fetch(url, expire, &opts.retry).await?;
+        if self.dry_run {
             return Ok(path);
+        }
+        let sig_path = match opts.signature_url(url)? {
+            Some(sig_url) => Some(self.fetch(&sig_url, expire, &opts.retry).await?),
+            None => None,
         };
+        verify::verify(&path, sig_path.as_deref(), opts)
+            .await
+            .map_err(|e| mlua::Error::external(e.context(format!("Failed to verify {url}"))))?;
+        Ok(path)
+    }
+    async fn fetch(
+        &self,
+        url: &str,
+        expire: Option<u64>,
+        retry: &RetryOptions,
+    ) -> Result<String, mlua::Error> {
+        let (path, dl) = ops::Dl::new(self.lease().await, url, expire).await?;
+        if dl.is_none() {
+            return Ok(path);
+        }
         self.ctx()
+            .await
             .interactor
-            .log(format!("Download: {} -> {}", url.as_ref(), path))
+            .log(format!("Download: {} -> {}", url, path))
             .await;
         if self.dry_run {
             return Ok(path);
         }
-        dl.execute(&path).await?;
+        self.retry(&format!("download {url}"), retry, || async {
+            let (_, dl) = ops::Dl::new(self.lease().await, url, expire).await?;
+            let res = match dl {
+                Some(dl) => dl.execute(&path).await,
+                None => Ok(()),
+            };
+            if res.is_err() {
+                // drop the partial file so the next attempt starts over
+                let _ = tokio::fs::remove_file(proxy::expand_local(&path)).await;
+            }
+            res
+        })
+        .await?;
         Ok(path)
     }
 }
 
+#[derive(serde::Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+enum SyncMode {
+    #[default]
+    OneWay,
+    Bidirectional,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct SyncOptions {
+    #[serde(default)]
+    mode: SyncMode,
+    scan: Option<usize>,
+    transfer: Option<usize>,
+    #[serde(flatten)]
+    retry: RetryOptions,
+}
+
+impl FromLua for SyncOptions {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        lua.from_value(value)
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum PathPair {
+    Named { src: String, dst: String },
+    Positional(String, String),
+}
+
 #[derive(serde::Deserialize)]
 enum SyncPath {
     Single(String),
     Multiple(Vec<String>),
+    Pairs(Vec<(String, String)>),
 }
 
 impl FromLua for SyncPath {
@@ -185,36 +494,92 @@ impl FromLua for SyncPath {
         if let Some(s) = value.as_string() {
             return Ok(SyncPath::Single(s.to_str()?.to_string()));
         }
+        let Some(t) = value.as_table() else {
+            return Err(conversion_error(
+                value.type_name(),
+                "SyncPath",
+                Some("expected a path, a list of paths or path pairs"),
+            ));
+        };
+        if t.raw_len() == 0 && !t.is_empty() {
+            let map: std::collections::BTreeMap<String, String> = lua.from_value(value)?;
+            return Ok(SyncPath::Pairs(map.into_iter().collect()));
+        }
+        if t.raw_get::<Value>(1)?.is_table() {
+            let pairs: Vec<PathPair> = lua.from_value(value)?;
+            return Ok(SyncPath::Pairs(
+                pairs
+                    .into_iter()
+                    .map(|p| match p {
+                        PathPair::Named { src, dst } | PathPair::Positional(src, dst) => (src, dst),
+                    })
+                    .collect(),
+            ));
+        }
         let vec: Vec<String> = lua.from_value(value)?;
         Ok(SyncPath::Multiple(vec))
     }
 }
 
+impl SyncPath {
+    fn pairs(self, dst: Option<SyncPath>) -> mlua::Result<Vec<(String, String)>> {
+        let invalid = |message: String| conversion_error("Value", "SyncPath", Some(message));
+        Ok(match (self, dst) {
+            (SyncPath::Single(s), Some(SyncPath::Single(d))) => vec![(s, d)],
+            (SyncPath::Single(s), Some(SyncPath::Multiple(d))) => {
+                d.into_iter().map(|dp| (s.clone(), dp)).collect()
+            }
+            (SyncPath::Multiple(s), Some(SyncPath::Multiple(d))) => {
+                if s.len() != d.len() {
+                    return Err(invalid(format!(
+                        "{} src paths but {} dst paths",
+                        s.len(),
+                        d.len()
+                    )));
+                }
+                s.into_iter().zip(d).collect()
+            }
+            (SyncPath::Pairs(pairs), None) => pairs,
+            (SyncPath::Pairs(_), Some(_)) => {
+                return Err(invalid(
+                    "dst_path must be nil when src_path holds pairs".into(),
+                ));
+            }
+            (_, None) => return Err(invalid("dst_path required".into())),
+            _ => {
+                return Err(invalid(
+                    "expected a single dst_path, or as many dst paths as src paths".into(),
+                ));
+            }
+        })
+    }
+}
+
 impl UserData for ContextWrapper {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
         methods.add_async_method(
             "sync",
             |_,
              this,
-             (src, src_path, dst, dst_path, confirm): (
+             (src, src_path, dst, dst_path, confirm, opts): (
                 String,
                 SyncPath,
                 String,
-                SyncPath,
-                Option<String>,
+                Option<SyncPath>,
+                Confirm,
+                Option<SyncOptions>,
             )| async move {
-                let pairs: Vec<(String, String)> = match (src_path, dst_path) {
-                    (SyncPath::Single(s), SyncPath::Single(d)) => vec![(s, d)],
-                    (SyncPath::Single(s), SyncPath::Multiple(d)) => {
-                        d.into_iter().map(|dp| (s.clone(), dp)).collect()
-                    }
-                    _ => Err(conversion_error(
-                        "Value",
-                        "SyncPath",
-                        Some("Single dst_path required"),
-                    ))?,
-                };
-                Ok(this.sync(&src, &dst, &pairs, confirm.as_deref()).await?)
+                let pairs = src_path.pairs(dst_path)?;
+                let opts = opts.unwrap_or_default();
+                if opts.mode == SyncMode::Bidirectional {
+                    let (changed, conflicts) =
+                        this.sync_bidirectional(&src, &dst, &pairs, &opts).await?;
+                    return Ok((changed, Some(conflicts)));
+                }
+                Ok((
+                    this.sync(&src, &dst, &pairs, &confirm.0, &opts).await?,
+                    None,
+                ))
             },
         );
 
@@ -232,12 +597,13 @@ impl UserData for ContextWrapper {
 
         methods.add_async_method(
             "dl",
-            |_, this, (url, expire): (String, Option<Value>)| async move {
+            |_, this, (url, expire, opts): (String, Option<Value>, Option<DlOptions>)| async move {
                 this.dl(
                     url,
                     expire
                         .map(|expire| this.lua().from_value(expire))
                         .transpose()?,
+                    &opts.unwrap_or_default(),
                 )
                 .await
             },
@@ -257,14 +623,52 @@ impl UserData for ContextWrapper {
             }
         });
 
+        methods.add_method("spawn", |_, this, (f, args): (Function, MultiValue)| {
+            let task = task::Task::spawn(f, args);
+            let mut tasks = this.tasks.borrow_mut();
+            tasks.retain(task::Task::is_pending);
+            tasks.push(task.clone());
+            Ok(task)
+        });
+        methods.add_async_method("join", |lua, _, tasks: Value| async move {
+            match tasks {
+                Value::UserData(ud) => {
+                    let task = ud.borrow::<task::Task>()?.clone();
+                    task.join().await
+                }
+                Value::Table(tasks) => task::join(&lua, task::tasks_from_table(tasks)?)
+                    .await?
+                    .into_lua_multi(&lua),
+                _ => Err(conversion_error(
+                    tasks.type_name(),
+                    "Task",
+                    Some("expected a task or a list of tasks"),
+                )),
+            }
+        });
+        methods.add_async_method("all", |lua, _, fs: Vec<Function>| async move {
+            let tasks = fs
+                .into_iter()
+                .map(|f| task::Task::spawn(f, MultiValue::new()))
+                .collect();
+            task::join(&lua, tasks).await
+        });
+
         methods.add_method("dot", |_, this, ()| Ok(dot::Dot::new(this.clone())));
         methods.add_method("um", |_, this, ()| Ok(user::UserManager::new(this.clone())));
         methods.add_method("pm", |_, this, ()| Ok(pm::Pm::new(this.clone())));
     }
 }
 
-pub fn register(ctx: dv_wrap::Context, dry_run: bool) -> mlua::Result<ContextWrapper> {
-    let ctx = ContextWrapper::new(ctx, dry_run);
+pub fn register(
+    ctx: dv_wrap::Context,
+    state: State,
+    inventory: Inventory,
+    retry: RetryOptions,
+    jobs: usize,
+    dry_run: bool,
+) -> mlua::Result<ContextWrapper> {
+    let ctx = ContextWrapper::new(ctx, state, inventory, retry, jobs, dry_run);
     ctx.lua().globals().set("dv", ctx.clone())?;
     Ok(ctx)
 }
@@ -297,4 +701,46 @@ mod tests {
             _ => panic!("Expected Multiple variant"),
         }
     }
+
+    #[test]
+    fn sync_path_pairs() {
+        let pairs = |src: &str, dst: Option<&str>| {
+            let src = sync_path_des_suc_f(src).expect("Failed to deserialize");
+            let dst = dst.map(|d| sync_path_des_suc_f(d).expect("Failed to deserialize"));
+            src.pairs(dst)
+        };
+        let expected = vec![
+            ("a".to_string(), "x".to_string()),
+            ("b".to_string(), "y".to_string()),
+        ];
+        assert_eq!(
+            pairs("{'a', 'b'}", Some("{'x', 'y'}")).expect("Failed to pair"),
+            expected
+        );
+        assert_eq!(
+            pairs("{{src = 'a', dst = 'x'}, {'b', 'y'}}", None).expect("Failed to pair"),
+            expected
+        );
+        assert_eq!(
+            pairs("{a = 'x', b = 'y'}", None).expect("Failed to pair"),
+            expected
+        );
+        assert!(pairs("{'a', 'b'}", Some("{'x'}")).is_err());
+        assert!(pairs("{'a', 'b'}", Some("'x'")).is_err());
+        assert!(pairs("{a = 'x'}", Some("'x'")).is_err());
+    }
+
+    #[test]
+    fn sync_options_serde() {
+        let lua = mlua::Lua::new();
+        let val = lua
+            .load("{transfer = 16, retries = 1}")
+            .eval::<mlua::Value>()
+            .expect("Failed to load");
+        let opts = super::SyncOptions::from_lua(val, &lua).expect("Failed to deserialize");
+        assert_eq!(opts.mode, super::SyncMode::OneWay);
+        assert_eq!(opts.scan, None);
+        assert_eq!(opts.transfer, Some(16));
+        assert_eq!(opts.retry.retries, Some(1));
+    }
 }
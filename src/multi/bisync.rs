@@ -0,0 +1,253 @@
+use super::SyncOptions;
+use super::dev::*;
+use crate::util::sh_quote;
+use anyhow::bail;
+use dv_api::process::ScriptExecutor;
+use dv_wrap::ops::SyncOpt;
+use std::collections::{BTreeMap, BTreeSet};
+
+const BASE_NS: &str = "sync";
+
+type Fingerprint = String;
+type Listing = BTreeMap<String, Fingerprint>;
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    ToDst,
+    ToSrc,
+    DeleteDst,
+    DeleteSrc,
+    Conflict,
+}
+
+/// compares both sides with the base, returning the actions and the base after applying them
+fn plan(src: &Listing, dst: &Listing, base: &Listing) -> (Vec<(String, Action)>, Listing) {
+    let mut actions = Vec::new();
+    let mut next = Listing::new();
+    let files: BTreeSet<&String> = src.keys().chain(dst.keys()).chain(base.keys()).collect();
+    for file in files {
+        let (s, d, b) = (src.get(file), dst.get(file), base.get(file));
+        let (action, result) = if s == d {
+            (None, s)
+        } else if s == b {
+            (
+                Some(if d.is_some() {
+                    Action::ToSrc
+                } else {
+                    Action::DeleteSrc
+                }),
+                d,
+            )
+        } else if d == b {
+            (
+                Some(if s.is_some() {
+                    Action::ToDst
+                } else {
+                    Action::DeleteDst
+                }),
+                s,
+            )
+        } else {
+            (Some(Action::Conflict), b)
+        };
+        if let Some(action) = action {
+            actions.push((file.clone(), action));
+        }
+        if let Some(result) = result {
+            next.insert(file.clone(), result.clone());
+        }
+    }
+    (actions, next)
+}
+
+fn sh_path(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => format!("\"$HOME\"/{}", sh_quote(rest)),
+        None if path == "~" => "\"$HOME\"".to_string(),
+        None => sh_quote(path),
+    }
+}
+
+fn join(root: &str, file: &str) -> String {
+    format!("{}/{}", root.trim_end_matches('/'), file)
+}
+
+impl ContextWrapper {
+    async fn listing(&self, uid: &str, root: &str, opts: &SyncOptions) -> Result<Listing> {
+        let cmd = format!(
+            "cd {} 2>/dev/null || exit 0; find . -type f -exec cksum {{}} +",
+            sh_path(root)
+        );
+        let output = self
+            .exec(uid, &cmd, true, Some(ScriptExecutor::Sh), &opts.retry)
+            .await?;
+        if output.code != 0 {
+            bail!(
+                "Failed to list {uid}:{root}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let mut listing = Listing::new();
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let mut parts = line.splitn(3, ' ');
+            let (Some(crc), Some(size), Some(file)) = (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            let file = file.strip_prefix("./").unwrap_or(file);
+            listing.insert(file.to_string(), format!("{crc} {size}"));
+        }
+        Ok(listing)
+    }
+    async fn delete(&self, uid: &str, files: &[String], opts: &SyncOptions) -> Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+        let cmd = files
+            .iter()
+            .map(|f| sh_path(f))
+            .fold("rm -f --".to_string(), |cmd, f| cmd + " " + &f);
+        let output = self
+            .exec(uid, &cmd, true, Some(ScriptExecutor::Sh), &opts.retry)
+            .await?;
+        if output.code != 0 {
+            bail!(
+                "Failed to delete on {uid}: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+    /// propagates changes made on either side since the last bidirectional sync of the same
+    /// pair, returning whether anything changed and the files changed on both sides
+    pub(super) async fn sync_bidirectional(
+        &self,
+        src: &str,
+        dst: &str,
+        pairs: &[(String, String)],
+        opts: &SyncOptions,
+    ) -> Result<(bool, Vec<String>)> {
+        // the last synced state decides which side changed, their mtimes may not agree
+        const COPY: [SyncOpt; 2] = [SyncOpt::OVERWRITE, SyncOpt::UPLOAD];
+        let (mut changed, mut conflicts) = (false, Vec::new());
+        for (src_root, dst_root) in pairs {
+            let key = format!("{src}:{src_root}|{dst}:{dst_root}");
+            let base: Listing = self.state().get(BASE_NS, &key)?.unwrap_or_default();
+            let src_files = self.listing(src, src_root, opts).await?;
+            let dst_files = self.listing(dst, dst_root, opts).await?;
+            let (actions, next) = plan(&src_files, &dst_files, &base);
+
+            let (mut to_dst, mut to_src) = (Vec::new(), Vec::new());
+            let (mut delete_dst, mut delete_src) = (Vec::new(), Vec::new());
+            for (file, action) in actions {
+                let (s, d) = (join(src_root, &file), join(dst_root, &file));
+                match action {
+                    Action::ToDst => to_dst.push((s, d)),
+                    Action::ToSrc => to_src.push((d, s)),
+                    Action::DeleteDst => delete_dst.push(d),
+                    Action::DeleteSrc => delete_src.push(s),
+                    Action::Conflict => {
+                        self.ctx()
+                            .await
+                            .interactor
+                            .log(format!("Conflict: {src}:{s} <-> {dst}:{d}"))
+                            .await;
+                        conflicts.push(file);
+                    }
+                }
+            }
+            for (uid, files) in [(dst, &delete_dst), (src, &delete_src)] {
+                for file in files {
+                    self.ctx()
+                        .await
+                        .interactor
+                        .log(format!("Delete: {uid}:{file}"))
+                        .await;
+                }
+            }
+            if !to_dst.is_empty() {
+                changed |= self.sync(src, dst, &to_dst, &COPY, opts).await?;
+            }
+            if !to_src.is_empty() {
+                changed |= self.sync(dst, src, &to_src, &COPY, opts).await?;
+            }
+            changed |= !delete_dst.is_empty() || !delete_src.is_empty();
+            if self.dry_run {
+                continue;
+            }
+            self.delete(dst, &delete_dst, opts).await?;
+            self.delete(src, &delete_src, opts).await?;
+            let mut state = self.state_mut();
+            if next.is_empty() {
+                state.remove(BASE_NS, &key)?;
+            } else {
+                state.set(BASE_NS, &key, &next)?;
+            }
+        }
+        Ok((changed, conflicts))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Listing, plan, sh_path};
+
+    fn listing(files: &[(&str, &str)]) -> Listing {
+        files
+            .iter()
+            .map(|(f, c)| (f.to_string(), c.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn bisync_plan() {
+        let base = listing(&[
+            ("same", "1"),
+            ("src", "1"),
+            ("dst", "1"),
+            ("both", "1"),
+            ("gone", "1"),
+        ]);
+        let src = listing(&[
+            ("same", "1"),
+            ("src", "2"),
+            ("dst", "1"),
+            ("both", "2"),
+            ("new", "1"),
+        ]);
+        let dst = listing(&[
+            ("same", "1"),
+            ("src", "1"),
+            ("dst", "2"),
+            ("both", "3"),
+            ("gone", "1"),
+        ]);
+        let (actions, next) = plan(&src, &dst, &base);
+        assert_eq!(
+            actions,
+            vec![
+                ("both".to_string(), Action::Conflict),
+                ("dst".to_string(), Action::ToSrc),
+                ("gone".to_string(), Action::DeleteDst),
+                ("new".to_string(), Action::ToDst),
+                ("src".to_string(), Action::ToDst),
+            ]
+        );
+        assert_eq!(
+            next,
+            listing(&[
+                ("same", "1"),
+                ("src", "2"),
+                ("dst", "2"),
+                ("both", "1"),
+                ("new", "1")
+            ])
+        );
+    }
+
+    #[test]
+    fn bisync_sh_path() {
+        assert_eq!(sh_path("~/notes"), "\"$HOME\"/'notes'");
+        assert_eq!(sh_path("/tmp/it's"), r#"'/tmp/it'\''s'"#);
+    }
+}
@@ -1,53 +1,100 @@
-use crate::util::sync_opts;
+use crate::util::Confirm;
 
 use super::dev::*;
-use dv_wrap::ops::{DotConfig, DotUtil};
+use super::shared::Lease;
+use dv_wrap::{
+    Context,
+    ops::{DotConfig, DotUtil, SyncOpt},
+};
 
+enum Added {
+    Schema(String, String),
+    Source(String, String),
+}
+
+/// Schemas and sources are replayed into a dv-wrap util for each operation, as the util keeps
+/// the context leased for as long as it lives.
 pub struct Dot {
-    dot: DotUtil<ContextWrapper>,
+    ctx: ContextWrapper,
+    copy_action: Vec<SyncOpt>,
+    added: Vec<Added>,
 }
 impl Dot {
     pub fn new(ctx: ContextWrapper) -> Self {
         Self {
-            dot: DotUtil::new(ctx, Vec::new()),
+            ctx,
+            copy_action: Vec::new(),
+            added: Vec::new(),
+        }
+    }
+    async fn util(&self) -> Result<DotUtil<Lease<Context>>> {
+        let mut dot = DotUtil::new(self.ctx.lease().await, Vec::new());
+        dot.copy_action = self.copy_action.clone();
+        for added in &self.added {
+            match added {
+                Added::Schema(user, path) => {
+                    dot.add_schema(user, path).await?;
+                }
+                Added::Source(user, path) => {
+                    dot.add_source(user, path).await;
+                }
+            }
         }
+        Ok(dot)
     }
 }
 
+/// dotfile schemas and sources are resolved by dv-wrap, which doesn't know proxy users
+fn check_user(ctx: &ContextWrapper, uid: &str) -> Result<()> {
+    if ctx.proxy(uid).is_some() {
+        anyhow::bail!(
+            "Dot needs a cur or ssh user, {uid} is reached through a proxy; sync it with dv:sync instead"
+        );
+    }
+    Ok(())
+}
+
 impl UserData for Dot {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method_mut(
-            "confirm",
-            |_, mut this, confirm: Option<String>| async move {
-                this.dot.copy_action = sync_opts(&confirm.unwrap_or_default())?;
-                Ok(())
-            },
-        );
+        methods.add_async_method_mut("confirm", |_, mut this, confirm: Confirm| async move {
+            this.copy_action = confirm.0;
+            Ok(())
+        });
 
         methods.add_async_method_mut(
             "add_schema",
             |_, mut this, (user, path): (String, String)| async move {
-                Ok(this.dot.add_schema(&user, &path).await?)
+                check_user(&this.ctx, &user)?;
+                let res = this.util().await?.add_schema(&user, &path).await?;
+                this.added.push(Added::Schema(user, path));
+                Ok(res)
             },
         );
 
         methods.add_async_method_mut(
             "add_source",
             |_, mut this, (user, path): (String, String)| async move {
-                Ok(this.dot.add_source(&user, &path).await)
+                check_user(&this.ctx, &user)?;
+                let res = this.util().await?.add_source(&user, &path).await;
+                this.added.push(Added::Source(user, path));
+                Ok(res)
             },
         );
 
         methods.add_async_method(
             "sync",
             |_, this, (apps, dst): (Vec<String>, String)| async move {
-                let entries = this
-                    .dot
+                check_user(&this.ctx, &dst)?;
+                let dot = this.util().await?;
+                let entries = dot
                     .sync(apps.into_iter().map(DotConfig::new).collect(), &dst)
                     .await?;
                 let mut res = false;
                 for e in &entries {
-                    res |= this.dot.ctx.sync_impl(&e.src, &e.dst, &e.entries).await?;
+                    res |= this
+                        .ctx
+                        .sync_impl(&e.src, &e.dst, &e.entries, &Default::default())
+                        .await?;
                 }
                 Ok(res)
             },
@@ -55,13 +102,17 @@ impl UserData for Dot {
         methods.add_async_method(
             "upload",
             |_, this, (apps, dst): (Vec<String>, String)| async move {
-                let entries = this
-                    .dot
+                check_user(&this.ctx, &dst)?;
+                let dot = this.util().await?;
+                let entries = dot
                     .upload(apps.into_iter().map(DotConfig::new).collect(), &dst)
                     .await?;
                 let mut res = false;
                 for e in &entries {
-                    res |= this.dot.ctx.sync_impl(&e.src, &e.dst, &e.entries).await?;
+                    res |= this
+                        .ctx
+                        .sync_impl(&e.src, &e.dst, &e.entries, &Default::default())
+                        .await?;
                 }
                 Ok(res)
             },
@@ -0,0 +1,175 @@
+use super::dev::*;
+use anyhow::bail;
+use dv_api::process::ScriptExecutor;
+
+const NS: &str = "facts";
+
+const GATHER: &str = r#"
+echo "machine=$(uname -m)"
+echo "kernel=$(uname -r)"
+echo "system=$(uname -s)"
+if [ -r /etc/os-release ]; then
+  (. /etc/os-release; echo "distro=$ID"; echo "version=$VERSION_ID")
+elif command -v sw_vers >/dev/null 2>&1; then
+  echo "distro=macos"; echo "version=$(sw_vers -productVersion)"
+fi
+echo "hostname=$(hostname 2>/dev/null || uname -n)"
+echo "cpus=$(getconf _NPROCESSORS_ONLN 2>/dev/null || nproc 2>/dev/null)"
+if [ -r /proc/meminfo ]; then
+  echo "memory=$(awk '/^MemTotal:/ { printf "%.0f", $2 * 1024 }' /proc/meminfo)"
+else
+  echo "memory=$(sysctl -n hw.memsize 2>/dev/null)"
+fi
+df -Pk / 2>/dev/null | awk 'NR == 2 { printf "disk=%.0f %.0f %.0f\n", $2 * 1024, $3 * 1024, $4 * 1024 }'
+echo "shell=$SHELL"
+echo "home=$HOME"
+for pm in apt dnf yum pacman yay paru zypper apk brew; do
+  command -v "$pm" >/dev/null 2>&1 && echo "pm=$pm"
+done
+true
+"#;
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Disk {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Facts {
+    /// normalized architecture, e.g. `amd64`, `arm64`
+    pub arch: String,
+    /// raw `uname -m`, e.g. `x86_64`, `aarch64`
+    pub machine: String,
+    pub kernel: String,
+    pub system: String,
+    pub distro: Option<String>,
+    pub version: Option<String>,
+    pub hostname: String,
+    pub cpus: Option<u32>,
+    pub memory: Option<u64>,
+    pub disk: Option<Disk>,
+    pub shell: Option<String>,
+    pub home: Option<String>,
+    pub pms: Vec<String>,
+}
+
+fn normalize_arch(machine: &str) -> &str {
+    match machine {
+        "x86_64" | "amd64" => "amd64",
+        "aarch64" | "arm64" => "arm64",
+        "i386" | "i486" | "i586" | "i686" => "386",
+        "armv7l" | "armv7" => "armv7",
+        "armv6l" => "armv6",
+        "riscv64" => "riscv64",
+        _ => machine,
+    }
+}
+
+impl Facts {
+    pub fn parse(output: &str) -> Self {
+        let mut facts = Facts::default();
+        for line in output.lines() {
+            let Some((key, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match key {
+                "machine" => {
+                    facts.arch = normalize_arch(value).to_string();
+                    facts.machine = value.to_string();
+                }
+                "kernel" => facts.kernel = value.to_string(),
+                "system" => facts.system = value.to_lowercase(),
+                "distro" => facts.distro = Some(value.to_string()),
+                "version" => facts.version = Some(value.to_string()),
+                "hostname" => facts.hostname = value.to_string(),
+                "cpus" => facts.cpus = value.parse().ok(),
+                "memory" => facts.memory = value.parse().ok(),
+                "disk" => {
+                    let mut it = value.split_whitespace().map(|v| v.parse::<u64>());
+                    if let (Some(Ok(total)), Some(Ok(used)), Some(Ok(available))) =
+                        (it.next(), it.next(), it.next())
+                    {
+                        facts.disk = Some(Disk {
+                            total,
+                            used,
+                            available,
+                        });
+                    }
+                }
+                "shell" => facts.shell = Some(value.to_string()),
+                "home" => facts.home = Some(value.to_string()),
+                "pm" => facts.pms.push(value.to_string()),
+                _ => {}
+            }
+        }
+        facts
+    }
+
+    pub async fn get(ctx: &ContextWrapper, uid: &str, refresh: bool) -> Result<Self> {
+        if !refresh && let Some(facts) = ctx.state().get(NS, uid)? {
+            return Ok(facts);
+        }
+        // the gather script needs a posix shell
+        if ctx.user_var(uid, "os").await.as_deref() == Some("windows") {
+            bail!("Gathering facts on {uid} is unsupported, as it runs windows");
+        }
+        ctx.ctx()
+            .await
+            .interactor
+            .log(format!("Gather facts on {}", uid))
+            .await;
+        let output = ctx
+            .exec(
+                uid,
+                GATHER,
+                true,
+                Some(ScriptExecutor::Sh),
+                &Default::default(),
+            )
+            .await?;
+        let facts = Facts::parse(&String::from_utf8_lossy(&output.stdout));
+        ctx.state_mut().set(NS, uid, &facts)?;
+        Ok(facts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Disk, Facts};
+
+    #[test]
+    fn facts_parse() {
+        let facts = Facts::parse(
+            "machine=aarch64\nkernel=6.1.0\nsystem=Linux\ndistro=debian\nversion=12\n\
+             hostname=rt\ncpus=4\nmemory=8388608000\ndisk=1000 400 600\nshell=/bin/bash\n\
+             home=/home/km0e\npm=apt\npm=brew\ncpus=\n",
+        );
+        assert_eq!(facts.arch, "arm64");
+        assert_eq!(facts.machine, "aarch64");
+        assert_eq!(facts.system, "linux");
+        assert_eq!(facts.distro.as_deref(), Some("debian"));
+        assert_eq!(facts.version.as_deref(), Some("12"));
+        assert_eq!(facts.cpus, Some(4));
+        assert_eq!(facts.memory, Some(8388608000));
+        assert_eq!(
+            facts.disk,
+            Some(Disk {
+                total: 1000,
+                used: 400,
+                available: 600
+            })
+        );
+        assert_eq!(facts.pms, vec!["apt", "brew"]);
+
+        let facts = Facts::parse("machine=x86_64\ndisk=broken\n");
+        assert_eq!(facts.arch, "amd64");
+        assert!(facts.disk.is_none());
+        assert!(facts.distro.is_none());
+    }
+}
@@ -0,0 +1,206 @@
+use super::SyncOptions;
+use super::dev::*;
+use super::pm::{self, Packages, PmOptions};
+use super::retry::RetryOptions;
+use super::user::{ExecOptions, ensure_user};
+use crate::util::Confirm;
+use crate::util::conversion_error;
+use anyhow::anyhow;
+use futures::future::{join_all, try_join_all};
+use mlua::{Lua, Table};
+use std::future::Future;
+
+#[derive(serde::Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Policy {
+    FailFast,
+    #[default]
+    Continue,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct GroupOptions {
+    #[serde(default)]
+    policy: Policy,
+}
+
+pub struct Group {
+    ctx: ContextWrapper,
+    members: Vec<String>,
+    policy: Policy,
+}
+
+impl Group {
+    pub async fn new(
+        ctx: ContextWrapper,
+        name: &str,
+        members: Vec<String>,
+        opts: GroupOptions,
+    ) -> mlua::Result<Self> {
+        for uid in &members {
+            if !ensure_user(&ctx, uid).await? {
+                return Err(conversion_error(
+                    "string",
+                    "Group",
+                    Some(format!("member {uid} of group {name} not found")),
+                ));
+            }
+        }
+        Ok(Self {
+            ctx,
+            members,
+            policy: opts.policy,
+        })
+    }
+
+    async fn fan_out<'a, F, Fut>(&'a self, lua: &Lua, f: F) -> mlua::Result<Table>
+    where
+        F: Fn(&'a str) -> Fut,
+        Fut: Future<Output = mlua::Result<Table>> + 'a,
+    {
+        let results = lua.create_table()?;
+        let tasks = self.members.iter().map(|uid| {
+            let fut = f(uid.as_str());
+            async move { (uid, fut.await) }
+        });
+        if self.policy == Policy::FailFast {
+            let done = try_join_all(tasks.map(|task| async move {
+                match task.await {
+                    (uid, Ok(res)) => Ok((uid, res)),
+                    (uid, Err(e)) => Err(mlua::Error::external(anyhow!("{uid}: {e}"))),
+                }
+            }))
+            .await?;
+            for (uid, res) in done {
+                res.set("ok", true)?;
+                results.set(uid.as_str(), res)?;
+            }
+            return Ok(results);
+        }
+        for (uid, res) in join_all(tasks).await {
+            let res = match res {
+                Ok(res) => {
+                    res.set("ok", true)?;
+                    res
+                }
+                Err(e) => {
+                    let res = lua.create_table()?;
+                    res.set("ok", false)?;
+                    res.set("err", e.to_string())?;
+                    res
+                }
+            };
+            results.set(uid.as_str(), res)?;
+        }
+        Ok(results)
+    }
+}
+
+fn result_table(lua: &Lua, result: bool) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set("result", result)?;
+    Ok(t)
+}
+
+impl UserData for Group {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("members", |_, this, ()| Ok(this.members.clone()));
+        methods.add_async_method(
+            "exec",
+            |lua, this, (commands, opt): (String, Option<ExecOptions>)| async move {
+                let opt = opt.unwrap_or_default();
+                let (group, lua, commands, opt) = (&*this, &lua, &commands, &opt);
+                group
+                    .fan_out(lua, |uid| async move {
+                        group
+                            .ctx
+                            .ctx()
+                            .await
+                            .interactor
+                            .log(format!("Exec on {}: {}", uid, commands))
+                            .await;
+                        let t = lua.create_table()?;
+                        if group.ctx.dry_run {
+                            t.set("code", 0)?;
+                            return Ok(t);
+                        }
+                        let output = group
+                            .ctx
+                            .exec(uid, commands, opt.reply, opt.etor.clone(), &opt.retry)
+                            .await?;
+                        t.set("code", output.code)?;
+                        t.set("stdout", lua.create_string(output.stdout)?)?;
+                        t.set("stderr", lua.create_string(output.stderr)?)?;
+                        Ok(t)
+                    })
+                    .await
+            },
+        );
+        methods.add_async_method(
+            "write",
+            |lua, this, (path, content, retry): (String, String, Option<RetryOptions>)| async move {
+                let retry = retry.unwrap_or_default();
+                let (group, lua, path, content, retry) = (&*this, &lua, &path, &content, &retry);
+                group
+                    .fan_out(lua, |uid| async move {
+                        group
+                            .ctx
+                            .ctx()
+                            .await
+                            .interactor
+                            .log(format!("Write on {}: {}", uid, path))
+                            .await;
+                        if group.ctx.dry_run {
+                            return result_table(lua, true);
+                        }
+                        result_table(lua, group.ctx.write(uid, path, content, retry).await?)
+                    })
+                    .await
+            },
+        );
+        methods.add_async_method(
+            "sync",
+            |lua,
+             this,
+             (src, src_path, dst_path, confirm, opts): (
+                String,
+                String,
+                String,
+                Confirm,
+                Option<SyncOptions>,
+            )| async move {
+                let pairs = [(src_path, dst_path)];
+                let opts = opts.unwrap_or_default();
+                let (group, lua, src, pairs, confirm, opts) =
+                    (&*this, &lua, &src, &pairs, &confirm.0, &opts);
+                group
+                    .fan_out(lua, |uid| async move {
+                        result_table(lua, group.ctx.sync(src, uid, pairs, confirm, opts).await?)
+                    })
+                    .await
+            },
+        );
+        methods.add_async_method(
+            "install",
+            |lua, this, (packages, opts): (Packages, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                let (group, lua, packages, opts) = (&*this, &lua, &packages, &opts);
+                group
+                    .fan_out(lua, |uid| async move {
+                        let device = group.ctx.hid(uid).await.ok_or_else(|| {
+                            mlua::Error::external(anyhow!("{uid} doesn't belong to a device"))
+                        })?;
+                        let done = pm::install(&group.ctx, &device, packages, opts).await?;
+                        let t = result_table(lua, done)?;
+                        t.set(
+                            "packages",
+                            pm::report(lua, &group.ctx, &device, packages, opts, true, done)
+                                .await?,
+                        )?;
+                        Ok(t)
+                    })
+                    .await
+            },
+        );
+    }
+}
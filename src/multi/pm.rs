@@ -1,7 +1,50 @@
 use super::dev::*;
-use anyhow::bail;
+use super::facts::Facts;
+use super::proxy::{Output, expand_local};
+use super::retry::RetryOptions;
+use super::user::{UserWrapper, ensure_device, ensure_user, is_user};
+use crate::util::conversion_error;
+use anyhow::{anyhow, bail};
+use app::{Backend, Scope};
+use dv_api::process::ScriptExecutor;
 use dv_wrap::ops::Pm as OpPm;
-use std::ops::Deref;
+use kind::{Op, PmKind, SUDO_CHECK, WHERE};
+use lang::{LangKind, LangPm};
+use mlua::{FromLua, Lua, LuaSerdeExt, Table, Value};
+pub use package::Packages;
+use repo::RepoSpec;
+use std::{collections::HashMap, ops::Deref};
+
+mod app;
+mod kind;
+mod lang;
+mod package;
+mod repo;
+
+/// Options of the changing `Pm` methods, a boolean alone sets `confirm`.
+#[derive(serde::Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct PmOptions {
+    pub confirm: bool,
+    pub backend: Option<Backend>,
+    pub scope: Scope,
+    pub remote: Option<String>,
+    pub classic: bool,
+    #[serde(flatten)]
+    pub retry: RetryOptions,
+}
+
+impl FromLua for PmOptions {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        if let Some(b) = value.as_boolean() {
+            return Ok(PmOptions {
+                confirm: b,
+                ..Default::default()
+            });
+        }
+        lua.from_value(value)
+    }
+}
 
 pub struct Pm {
     ctx: ContextWrapper,
@@ -12,9 +55,26 @@ impl Pm {
     }
 }
 
+struct Who(String);
+
+impl FromLua for Who {
+    fn from_lua(value: Value, _: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            Value::String(s) => Ok(Who(s.to_str()?.to_string())),
+            Value::UserData(ud) => Ok(Who(ud.borrow::<UserWrapper>()?.uid().to_string())),
+            _ => Err(conversion_error(
+                value.type_name(),
+                "Who",
+                Some("expected a hid, a uid or a user"),
+            )),
+        }
+    }
+}
+
 async fn with_pm<'a: 'b, 'b, F, Fut, R>(
     ctx: &'a dv_wrap::Context,
     device: &str,
+    uid: &'a str,
     f: F,
 ) -> Result<R, mlua::Error>
 where
@@ -25,61 +85,788 @@ where
         let Some(dev) = ctx.devices.get(device) else {
             bail!("Device {device} not found in context")
         };
-        if let Some(sys) = &dev.system {
-            f(&dev.info.pm, sys, ctx).await
-        } else if let Some(user) = dev.users.first() {
-            f(&dev.info.pm, user, ctx).await
-        } else {
-            bail!("Device {device} has no system or users")
-        }
+        f(&dev.info.pm, uid, ctx).await
     }
     .await?)
 }
 
+/// the device `who` names, or the device of the user `who` along with it; a proxy user is a
+/// device of its own
+async fn select(ctx: &ContextWrapper, who: &str) -> Result<(String, Option<String>)> {
+    if ensure_device(ctx, who).await? {
+        return Ok((who.to_string(), None));
+    }
+    // only users known beforehand get connected
+    if !is_user(ctx, who).await {
+        bail!("{who} is neither a device nor a user");
+    }
+    ensure_user(ctx, who).await?;
+    if ctx.proxy(who).is_some() {
+        return Ok((who.to_string(), Some(who.to_string())));
+    }
+    let Some(hid) = ctx.hid(who).await else {
+        bail!("{who} doesn't belong to a device")
+    };
+    Ok((hid, Some(who.to_string())))
+}
+
+/// the user system packages are managed as: the one given, else the system account of the
+/// device, else its first user
+async fn system_uid(ctx: &ContextWrapper, who: &str) -> Result<String> {
+    let (device, uid) = select(ctx, who).await?;
+    if let Some(uid) = uid {
+        return Ok(uid);
+    }
+    let ctx = ctx.ctx().await;
+    let Some(dev) = ctx.devices.get(&device) else {
+        bail!("Device {device} not found in context")
+    };
+    let Some(uid) = dev.system.as_ref().or(dev.users.first()).cloned() else {
+        bail!("Device {device} has no system or users")
+    };
+    Ok(uid)
+}
+
+async fn user_uid(ctx: &ContextWrapper, who: &str) -> Result<String> {
+    let (device, uid) = select(ctx, who).await?;
+    if let Some(uid) = uid {
+        return Ok(uid);
+    }
+    let ctx = ctx.ctx().await;
+    let Some(dev) = ctx.devices.get(&device) else {
+        bail!("Device {device} not found in context")
+    };
+    let Some(uid) = dev.users.first().cloned() else {
+        bail!("Device {device} has no users")
+    };
+    Ok(uid)
+}
+
+async fn passes(
+    ctx: &ContextWrapper,
+    uid: &str,
+    check: &str,
+    retry: &RetryOptions,
+) -> Result<bool> {
+    let output = ctx
+        .exec(uid, check, true, Some(ScriptExecutor::Sh), retry)
+        .await?;
+    Ok(output.code == 0)
+}
+
+/// like [`target`], checking that the user, given or picked, can run the package manager; when
+/// sudo may `prompt` and needs a password, the device to run dv-wrap's package manager on, which
+/// asks for it through the interactor
+async fn privileged(
+    ctx: &ContextWrapper,
+    who: &str,
+    prompt: bool,
+    retry: &RetryOptions,
+) -> Result<(String, PmKind, Option<String>)> {
+    let (uid, kind) = target(ctx, who, retry).await?;
+    let Some(check) = kind.privilege_check() else {
+        return Ok((uid, kind, None));
+    };
+    if passes(ctx, &uid, check, retry).await? {
+        return Ok((uid, kind, None));
+    }
+    if !kind.needs_root() {
+        bail!(
+            "{} refuses to run as root, pick a regular user instead of {uid}",
+            kind.name()
+        );
+    }
+    // proxy users aren't known to dv-wrap
+    let hid = if prompt { ctx.hid(&uid).await } else { None };
+    match hid {
+        Some(hid) if passes(ctx, &uid, SUDO_CHECK, retry).await? => Ok((uid, kind, Some(hid))),
+        Some(_) => bail!(
+            "{uid} can't run {} as it is neither root nor able to sudo",
+            kind.name()
+        ),
+        None => bail!(
+            "{uid} can't run {} as it is neither root nor allowed to sudo without a password",
+            kind.name()
+        ),
+    }
+}
+
+async fn target(ctx: &ContextWrapper, who: &str, retry: &RetryOptions) -> Result<(String, PmKind)> {
+    let uid = system_uid(ctx, who).await?;
+    let kind = match ctx.user_var(&uid, "pm").await {
+        Some(name) => PmKind::from_name(&name).ok_or_else(|| anyhow!("Unsupported pm {name}"))?,
+        // facts need a posix shell
+        None if ctx.user_var(&uid, "os").await.as_deref() == Some("windows") => {
+            let output = ctx.exec(&uid, WHERE, true, None, retry).await?;
+            PmKind::from_where(&String::from_utf8_lossy(&output.stdout))?
+        }
+        None => Facts::get(ctx, &uid, false)
+            .await?
+            .pms
+            .iter()
+            .find_map(|pm| PmKind::from_name(pm))
+            .ok_or_else(|| anyhow!("No supported package manager found on {who}"))?,
+    };
+    Ok((uid, kind))
+}
+
+fn stdout(uid: &str, output: Output, ok: impl Fn(i32) -> bool) -> Result<String> {
+    if output.code != 0 && !ok(output.code) {
+        bail!(
+            "Package manager failed on {uid} ({}): {}",
+            output.code,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+async fn run(ctx: &ContextWrapper, uid: &str, cmd: &str, retry: &RetryOptions) -> Result<String> {
+    let output = ctx
+        .exec(uid, cmd, true, Some(ScriptExecutor::Sh), retry)
+        .await?;
+    stdout(uid, output, |_| false)
+}
+
+/// runs a command of `kind` in its shell; a `lookup`, a query or search, also succeeds with
+/// the exit codes telling that not every package was found
+async fn run_kind(
+    ctx: &ContextWrapper,
+    uid: &str,
+    kind: PmKind,
+    cmd: &str,
+    lookup: bool,
+    retry: &RetryOptions,
+) -> Result<String> {
+    let output = ctx.exec(uid, cmd, true, kind.executor(), retry).await?;
+    stdout(uid, output, |code| lookup && kind.none_found(code))
+}
+
+async fn confirm(
+    ctx: &ContextWrapper,
+    device: &str,
+    what: &str,
+    preview: Option<(Op, &[&str])>,
+    retry: &RetryOptions,
+) -> Result<bool> {
+    let mut shown = None;
+    if let Some((op, packages)) = preview {
+        let (uid, kind) = target(ctx, device, retry).await?;
+        if let Some(cmd) = kind.preview(op, packages) {
+            // simulations exit non-zero for a declined transaction, the output is all that matters
+            let output = ctx
+                .exec(&uid, &cmd, true, Some(ScriptExecutor::Sh), retry)
+                .await?;
+            shown = Some(
+                String::from_utf8_lossy(&output.stdout)
+                    .trim_end()
+                    .to_string(),
+            );
+        }
+    }
+    // previews run concurrently, but each is shown right before its own question
+    let _prompt = ctx.prompt.lock().await;
+    if let Some(shown) = shown {
+        ctx.ctx().await.interactor.log(shown).await;
+    }
+    let answer = ctx
+        .ctx()
+        .await
+        .interactor
+        .confirm(format!("{what} on {device}?"), &["y/yes", "n/no"])
+        .await?;
+    Ok(answer == 0)
+}
+
+/// maps logical package names to the ones of `backend`, or of the package manager on `device`,
+/// which has to be detected when any name has aliases
+async fn resolve(
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &[&str],
+    backend: Option<Backend>,
+    retry: &RetryOptions,
+) -> Result<Vec<String>> {
+    let aliased = {
+        let aliases = ctx.aliases.borrow();
+        packages.iter().any(|p| aliases.contains_key(*p))
+    };
+    // only detect the package manager when there is something to map
+    let keys = match backend {
+        Some(backend) => Some(backend.alias_keys()),
+        None if aliased => Some(target(ctx, device, retry).await?.1.alias_keys()),
+        None => None,
+    };
+    let aliases = ctx.aliases.borrow();
+    Ok(packages
+        .iter()
+        .map(|p| {
+            keys.and_then(|keys| {
+                let alias = aliases.get(*p)?;
+                keys.iter().find_map(|k| alias.get(*k))
+            })
+            .cloned()
+            .unwrap_or_else(|| p.to_string())
+        })
+        .collect())
+}
+
+async fn specs(
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &Packages,
+    retry: &RetryOptions,
+) -> Result<Vec<String>> {
+    let resolved = resolve(ctx, device, &packages.names(), None, retry).await?;
+    if packages.0.iter().all(|p| p.version.is_none()) {
+        return Ok(resolved);
+    }
+    let (_, kind) = target(ctx, device, retry).await?;
+    resolved
+        .into_iter()
+        .zip(&packages.0)
+        .map(|(name, p)| match &p.version {
+            None => Ok(name),
+            Some(version) => kind
+                .pin(&name, version)
+                .ok_or_else(|| anyhow!("{} can't pin {name} to {version}", kind.name())),
+        })
+        .collect()
+}
+
+async fn query(
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &[&str],
+    opts: &PmOptions,
+) -> Result<Vec<(String, String)>> {
+    if let Some(backend) = opts.backend {
+        return app::query(ctx, device, backend, packages, opts).await;
+    }
+    let (uid, kind) = target(ctx, device, &opts.retry).await?;
+    let output = run_kind(ctx, &uid, kind, &kind.query(packages), true, &opts.retry).await?;
+    Ok(kind.parse_query(&output, packages))
+}
+
+pub async fn install(
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &Packages,
+    opts: &PmOptions,
+) -> mlua::Result<bool> {
+    if let Some(backend) = opts.backend {
+        return app::change(ctx, device, backend, Op::Install, packages, opts).await;
+    }
+    let specs = specs(ctx, device, packages, &opts.retry).await?;
+    let packages = &specs.join(" ");
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!("Install on {}: {}", device, packages))
+        .await;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    let specs: Vec<&str> = specs.iter().map(String::as_str).collect();
+    let preview = Some((Op::Install, specs.as_slice()));
+    if opts.confirm && !confirm(ctx, device, "Install", preview, &opts.retry).await? {
+        return Ok(false);
+    }
+    let (uid, kind, prompted) = privileged(ctx, device, true, &opts.retry).await?;
+    if let Some(hid) = prompted {
+        return ctx
+            .retry(&format!("install on {device}"), &opts.retry, || async {
+                let ctx = ctx.ctx().await;
+                with_pm(ctx.deref(), &hid, &uid, |pm, target, ctx| {
+                    pm.install(ctx, target, packages, true)
+                })
+                .await
+            })
+            .await;
+    }
+    let cmd = kind.change(Op::Install, &specs);
+    run_kind(ctx, &uid, kind, &cmd, false, &opts.retry).await?;
+    Ok(true)
+}
+
+pub async fn remove(
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &Packages,
+    opts: &PmOptions,
+) -> mlua::Result<bool> {
+    if let Some(backend) = opts.backend {
+        return app::change(ctx, device, backend, Op::Remove, packages, opts).await;
+    }
+    let packages = resolve(ctx, device, &packages.names(), None, &opts.retry).await?;
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!("Remove on {}: {}", device, packages.join(" ")))
+        .await;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    let packages: Vec<&str> = packages.iter().map(String::as_str).collect();
+    let preview = Some((Op::Remove, packages.as_slice()));
+    if opts.confirm && !confirm(ctx, device, "Remove", preview, &opts.retry).await? {
+        return Ok(false);
+    }
+    let (uid, kind, _) = privileged(ctx, device, false, &opts.retry).await?;
+    let cmd = kind.change(Op::Remove, &packages);
+    run_kind(ctx, &uid, kind, &cmd, false, &opts.retry).await?;
+    Ok(true)
+}
+
+pub async fn upgrade(
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &Packages,
+    opts: &PmOptions,
+) -> mlua::Result<bool> {
+    if let Some(backend) = opts.backend {
+        return app::change(ctx, device, backend, Op::Upgrade, packages, opts).await;
+    }
+    let specs = specs(ctx, device, packages, &opts.retry).await?;
+    let packages = &specs.join(" ");
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!("Upgrade on {}: {}", device, packages))
+        .await;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    let specs: Vec<&str> = specs.iter().map(String::as_str).collect();
+    let preview = Some((Op::Upgrade, specs.as_slice()));
+    if opts.confirm && !confirm(ctx, device, "Upgrade", preview, &opts.retry).await? {
+        return Ok(false);
+    }
+    let (uid, kind, prompted) = privileged(ctx, device, true, &opts.retry).await?;
+    if let Some(hid) = prompted {
+        return ctx
+            .retry(&format!("upgrade on {device}"), &opts.retry, || async {
+                let ctx = ctx.ctx().await;
+                with_pm(ctx.deref(), &hid, &uid, |pm, target, ctx| {
+                    pm.upgrade(ctx, target, packages, true)
+                })
+                .await
+            })
+            .await;
+    }
+    let cmd = kind.change(Op::Upgrade, &specs);
+    run_kind(ctx, &uid, kind, &cmd, false, &opts.retry).await?;
+    Ok(true)
+}
+
+/// `{ok, version}` of each package by the name it was given, `ok` telling whether it is
+/// `present` as asked after an operation that returned `done`
+pub async fn report(
+    lua: &Lua,
+    ctx: &ContextWrapper,
+    device: &str,
+    packages: &Packages,
+    opts: &PmOptions,
+    present: bool,
+    done: bool,
+) -> mlua::Result<Table> {
+    let resolved = resolve(ctx, device, &packages.names(), opts.backend, &opts.retry).await?;
+    // nothing changed in a dry run, so the operation's result is all there is
+    let installed = if ctx.dry_run || packages.is_empty() {
+        None
+    } else {
+        let names: Vec<&str> = resolved.iter().map(String::as_str).collect();
+        Some(query(ctx, device, &names, opts).await?)
+    };
+    report_table(
+        lua,
+        packages,
+        &resolved,
+        installed.as_deref(),
+        present,
+        done,
+    )
+}
+
+fn report_table(
+    lua: &Lua,
+    packages: &Packages,
+    resolved: &[String],
+    installed: Option<&[(String, String)]>,
+    present: bool,
+    done: bool,
+) -> mlua::Result<Table> {
+    let res = lua.create_table()?;
+    for (package, name) in packages.0.iter().zip(resolved) {
+        let t = lua.create_table()?;
+        match installed {
+            None => t.set("ok", done)?,
+            Some(installed) => {
+                let versions: Vec<&String> = installed
+                    .iter()
+                    .filter(|(n, _)| n == name)
+                    .map(|(_, v)| v)
+                    .collect();
+                // flatpak installs branches side by side
+                let version = package
+                    .version
+                    .as_ref()
+                    .and_then(|want| versions.iter().find(|v| version_matches(v, want)))
+                    .or(versions.first())
+                    .map(|v| v.to_string());
+                let ok = match (&version, &package.version) {
+                    (Some(v), Some(want)) => present && version_matches(v, want),
+                    (Some(_), None) => present,
+                    (None, _) => !present,
+                };
+                t.set("ok", ok)?;
+                t.set("version", version)?;
+            }
+        }
+        res.set(package.name.as_str(), t)?;
+    }
+    Ok(res)
+}
+
+/// whether the installed version `have` is the one asked for, `want` being a whole prefix of
+/// its components, e.g. `14.1` is `14.1.1` and `14.1-2` but not `14.10.0`
+fn version_matches(have: &str, want: &str) -> bool {
+    let components = |v: &str| {
+        v.split(['.', '-', '+', '~', '_'])
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    // an epoch is only compared when asked for
+    let have = match have.split_once(':') {
+        Some((_, rest)) if !want.contains(':') => rest,
+        _ => have,
+    };
+    let (have, want) = (components(have), components(want));
+    want.len() <= have.len() && want.iter().zip(&have).all(|(w, h)| w == h)
+}
+
+#[derive(serde::Deserialize, Default)]
+struct EnsureSpec {
+    #[serde(default)]
+    present: Vec<String>,
+    #[serde(default)]
+    absent: Vec<String>,
+    #[serde(flatten)]
+    opts: PmOptions,
+}
+
+impl FromLua for EnsureSpec {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        lua.from_value(value)
+    }
+}
+
+fn drift(spec: &EnsureSpec, installed: &[(String, String)]) -> (Vec<String>, Vec<String>) {
+    let is_installed = |p: &String| installed.iter().any(|(name, _)| name == p);
+    let install = spec
+        .present
+        .iter()
+        .filter(|p| !is_installed(p))
+        .cloned()
+        .collect();
+    let remove = spec
+        .absent
+        .iter()
+        .filter(|p| is_installed(p))
+        .cloned()
+        .collect();
+    (install, remove)
+}
+
 impl UserData for Pm {
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_async_method_mut(
+        methods.add_method(
+            "alias",
+            |_, this, (name, names): (String, HashMap<String, String>)| {
+                this.ctx.aliases.borrow_mut().insert(name, names);
+                Ok(())
+            },
+        );
+        methods.add_method("load_aliases", |_, this, path: String| {
+            let content =
+                std::fs::read_to_string(expand_local(&path)).map_err(mlua::Error::external)?;
+            let aliases: HashMap<String, HashMap<String, String>> =
+                toml::from_str(&content).map_err(mlua::Error::external)?;
+            this.ctx.aliases.borrow_mut().extend(aliases);
+            Ok(())
+        });
+        for kind in LangKind::ALL {
+            methods.add_async_method(kind.name(), move |_, this, Who(device): Who| async move {
+                Ok(LangPm::new(this.ctx.clone(), device, kind).await?)
+            });
+        }
+        methods.add_async_method(
             "install",
-            |_, this, (device, packages): (String, String)| async move {
-                let ctx = this.ctx.ctx();
-                ctx.interactor
-                    .log(format!("Install on {}: {}", device, packages))
+            |lua, this, (Who(device), packages, opts): (Who, Packages, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                let done = install(&this.ctx, &device, &packages, &opts).await?;
+                report(&lua, &this.ctx, &device, &packages, &opts, true, done).await
+            },
+        );
+        methods.add_async_method(
+            "remove",
+            |lua, this, (Who(device), packages, opts): (Who, Packages, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                let done = remove(&this.ctx, &device, &packages, &opts).await?;
+                report(&lua, &this.ctx, &device, &packages, &opts, false, done).await
+            },
+        );
+        methods.add_async_method(
+            "ensure",
+            |lua, this, (Who(device), mut spec): (Who, EnsureSpec)| async move {
+                for list in [&mut spec.present, &mut spec.absent] {
+                    let names: Vec<&str> = list.iter().map(String::as_str).collect();
+                    let (backend, retry) = (spec.opts.backend, &spec.opts.retry);
+                    *list = resolve(&this.ctx, &device, &names, backend, retry).await?;
+                }
+                let wanted: Vec<&str> = spec
+                    .present
+                    .iter()
+                    .chain(&spec.absent)
+                    .map(String::as_str)
+                    .collect();
+                // an empty query would list every package
+                let installed = if wanted.is_empty() {
+                    Vec::new()
+                } else {
+                    query(&this.ctx, &device, &wanted, &spec.opts).await?
+                };
+                let (to_install, to_remove) = drift(&spec, &installed);
+                let diff = to_install
+                    .iter()
+                    .map(|p| format!("+{p}"))
+                    .chain(to_remove.iter().map(|p| format!("-{p}")))
+                    .collect::<Vec<_>>();
+                this.ctx
+                    .ctx()
+                    .await
+                    .interactor
+                    .log(if diff.is_empty() {
+                        format!("Packages on {} are up to date", device)
+                    } else {
+                        format!("Package drift on {}: {}", device, diff.join(" "))
+                    })
                     .await;
-                if this.ctx.dry_run {
-                    return Ok(true);
+                // a dry run or a declined transaction changes nothing
+                let mut installed = Vec::new();
+                if !to_install.is_empty() {
+                    let packages = Packages::from_names(&to_install);
+                    if install(&this.ctx, &device, &packages, &spec.opts).await?
+                        && !this.ctx.dry_run
+                    {
+                        installed = to_install;
+                    }
                 }
-                with_pm(ctx.deref(), &device, |pm, target, ctx| {
-                    pm.install(ctx, target, &packages, true)
-                })
+                let mut removed = Vec::new();
+                if !to_remove.is_empty() {
+                    let packages = Packages::from_names(&to_remove);
+                    if remove(&this.ctx, &device, &packages, &spec.opts).await? && !this.ctx.dry_run
+                    {
+                        removed = to_remove;
+                    }
+                }
+                let res = lua.create_table()?;
+                res.set("installed", installed)?;
+                res.set("removed", removed)?;
+                Ok(res)
+            },
+        );
+        methods.add_async_method(
+            "is_installed",
+            |_, this, (Who(device), package, opts): (Who, String, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                let package = resolve(&this.ctx, &device, &[&package], opts.backend, &opts.retry)
+                    .await?
+                    .remove(0);
+                Ok(!query(&this.ctx, &device, &[&package], &opts)
+                    .await?
+                    .is_empty())
+            },
+        );
+        methods.add_async_method(
+            "installed_version",
+            |_, this, (Who(device), package, opts): (Who, String, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                let package = resolve(&this.ctx, &device, &[&package], opts.backend, &opts.retry)
+                    .await?
+                    .remove(0);
+                let installed = query(&this.ctx, &device, &[&package], &opts).await?;
+                Ok(installed.into_iter().next().map(|(_, version)| version))
+            },
+        );
+        methods.add_async_method(
+            "list_installed",
+            |lua, this, (Who(device), opts): (Who, Option<PmOptions>)| async move {
+                let installed = query(&this.ctx, &device, &[], &opts.unwrap_or_default()).await?;
+                lua.create_table_from(installed)
+            },
+        );
+        methods.add_async_method(
+            "add_repo",
+            |_, this, (Who(device), spec): (Who, RepoSpec)| async move {
+                let what = format!("Add repo {} ({})", spec.name, spec.url);
+                repo::apply(
+                    &this.ctx,
+                    &device,
+                    what,
+                    |kind| repo::add(kind, &spec),
+                    &spec.retry,
+                )
                 .await
             },
         );
-        methods.add_async_method_mut("update", |_, this, device: String| async move {
-            let ctx = this.ctx.ctx();
-            ctx.interactor.log(format!("Update on {}", device)).await;
-            if this.ctx.dry_run {
-                return Ok(true);
-            }
-            with_pm(ctx.deref(), &device, |pm, target, ctx| {
-                pm.update(ctx, target, true)
-            })
-            .await
-        });
-        methods.add_async_method_mut(
-            "upgrade",
-            |_, this, (device, packages): (String, String)| async move {
-                let ctx = this.ctx.ctx();
-                ctx.interactor
-                    .log(format!("Upgrade on {}: {}", device, packages))
+        methods.add_async_method(
+            "remove_repo",
+            |_, this, (Who(device), name, retry): (Who, String, Option<RetryOptions>)| async move {
+                let what = format!("Remove repo {name}");
+                let retry = retry.unwrap_or_default();
+                repo::apply(
+                    &this.ctx,
+                    &device,
+                    what,
+                    |kind| repo::remove(kind, &name),
+                    &retry,
+                )
+                .await
+            },
+        );
+        methods.add_async_method(
+            "add_flatpak_remote",
+            |_, this, (Who(device), name, url, opts): (Who, String, String, Option<PmOptions>)| async move {
+                app::remote(&this.ctx, &device, &name, Some(&url), &opts.unwrap_or_default()).await
+            },
+        );
+        methods.add_async_method(
+            "remove_flatpak_remote",
+            |_, this, (Who(device), name, opts): (Who, String, Option<PmOptions>)| async move {
+                app::remote(&this.ctx, &device, &name, None, &opts.unwrap_or_default()).await
+            },
+        );
+        methods.add_async_method(
+            "flatpak_remotes",
+            |lua, this, (Who(device), opts): (Who, Option<PmOptions>)| async move {
+                let remotes = app::remotes(&this.ctx, &device, &opts.unwrap_or_default()).await?;
+                lua.create_table_from(remotes)
+            },
+        );
+        methods.add_async_method(
+            "search",
+            |lua, this, (Who(device), term, opts): (Who, String, Option<PmOptions>)| async move {
+                let retry = opts.unwrap_or_default().retry;
+                let (uid, kind) = target(&this.ctx, &device, &retry).await?;
+                let output =
+                    run_kind(&this.ctx, &uid, kind, &kind.search(&term), true, &retry).await?;
+                let found = lua.create_table()?;
+                for (name, description) in kind.parse_search(&output) {
+                    let t: Table = lua.create_table()?;
+                    t.set("name", name)?;
+                    t.set("description", description)?;
+                    found.push(t)?;
+                }
+                Ok(found)
+            },
+        );
+        methods.add_async_method(
+            "update",
+            |_, this, (Who(device), opts): (Who, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                if let Some(backend) = opts.backend {
+                    return app::update(&this.ctx, &device, backend, &opts).await;
+                }
+                this.ctx
+                    .ctx()
+                    .await
+                    .interactor
+                    .log(format!("Update on {}", device))
                     .await;
                 if this.ctx.dry_run {
                     return Ok(true);
                 }
-                with_pm(this.ctx.ctx().deref(), &device, |pm, target, ctx| {
-                    pm.upgrade(ctx, target, &packages, true)
-                })
-                .await
+                if opts.confirm && !confirm(&this.ctx, &device, "Update", None, &opts.retry).await?
+                {
+                    return Ok(false);
+                }
+                let (uid, kind, prompted) =
+                    privileged(&this.ctx, &device, true, &opts.retry).await?;
+                if let Some(hid) = prompted {
+                    return this
+                        .ctx
+                        .retry(&format!("update on {device}"), &opts.retry, || async {
+                            let ctx = this.ctx.ctx().await;
+                            with_pm(ctx.deref(), &hid, &uid, |pm, target, ctx| {
+                                pm.update(ctx, target, true)
+                            })
+                            .await
+                        })
+                        .await;
+                }
+                run_kind(&this.ctx, &uid, kind, &kind.update(), false, &opts.retry).await?;
+                Ok(true)
             },
         );
+        methods.add_async_method(
+            "upgrade",
+            |lua, this, (Who(device), packages, opts): (Who, Packages, Option<PmOptions>)| async move {
+                let opts = opts.unwrap_or_default();
+                let done = upgrade(&this.ctx, &device, &packages, &opts).await?;
+                report(&lua, &this.ctx, &device, &packages, &opts, true, done).await
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, EnsureSpec, PmOptions, Scope, drift, version_matches};
+    use mlua::FromLua;
+
+    #[test]
+    fn ensure_drift() {
+        let lua = mlua::Lua::new();
+        let val = lua
+            .load("{present = {'fd', 'ripgrep'}, absent = {'nano', 'vim'}, retries = 1, confirm = true, backend = 'flatpak', scope = 'user'}")
+            .eval::<mlua::Value>()
+            .expect("Failed to load");
+        let spec = EnsureSpec::from_lua(val, &lua).expect("Failed to deserialize");
+        assert_eq!(spec.opts.retry.retries, Some(1));
+        assert!(spec.opts.confirm);
+        assert_eq!(spec.opts.backend, Some(Backend::Flatpak));
+        assert_eq!(spec.opts.scope, Scope::User);
+        let installed = vec![
+            ("ripgrep".to_string(), "14.1.1".to_string()),
+            ("nano".to_string(), "7.2".to_string()),
+        ];
+        let (install, remove) = drift(&spec, &installed);
+        assert_eq!(install, vec!["fd".to_string()]);
+        assert_eq!(remove, vec!["nano".to_string()]);
+    }
+
+    #[test]
+    fn pm_version_matches() {
+        assert!(version_matches("14.1.1", "14.1"));
+        assert!(version_matches("14.1-2", "14.1"));
+        assert!(version_matches("1:2.43.0-1", "2.43"));
+        assert!(version_matches("1:2.43.0-1", "1:2.43.0"));
+        assert!(version_matches("stable", "stable"));
+        assert!(!version_matches("14.10.0", "14.1"));
+        assert!(!version_matches("14", "14.1"));
+        assert!(!version_matches("2:2.43.0", "1:2.43"));
+    }
+
+    #[test]
+    fn pm_options_bool() {
+        let lua = mlua::Lua::new();
+        let opts =
+            PmOptions::from_lua(mlua::Value::Boolean(true), &lua).expect("Failed to convert");
+        assert!(opts.confirm);
+        assert_eq!(opts.retry.retries, None);
+        let opts =
+            PmOptions::from_lua(mlua::Value::Boolean(false), &lua).expect("Failed to convert");
+        assert!(!opts.confirm);
     }
 }
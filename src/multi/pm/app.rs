@@ -0,0 +1,362 @@
+use super::kind::{Op, ROOT_CHECK, ROOT_PRELUDE, parse_query};
+use super::{Packages, PmOptions, confirm, passes, resolve, run, system_uid, user_uid};
+use crate::multi::dev::*;
+use crate::util::sh_quote;
+use anyhow::bail;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    Flatpak,
+    Snap,
+}
+
+#[derive(serde::Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    #[default]
+    System,
+    User,
+}
+
+fn names(pkgs: &[(&str, Option<&str>)]) -> String {
+    pkgs.iter()
+        .map(|(name, _)| sh_quote(name))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Flatpak => "flatpak",
+            Backend::Snap => "snap",
+        }
+    }
+    pub fn alias_keys(&self) -> &'static [&'static str] {
+        match self {
+            Backend::Flatpak => &["flatpak"],
+            Backend::Snap => &["snap"],
+        }
+    }
+    fn sudo(&self, scope: Scope) -> &'static str {
+        match (self, scope) {
+            (Backend::Flatpak, Scope::User) => "",
+            _ => "$SUDO ",
+        }
+    }
+    fn scope(scope: Scope) -> &'static str {
+        match scope {
+            Scope::System => "--system",
+            Scope::User => "--user",
+        }
+    }
+    fn flatpak_refs(pkgs: &[(&str, Option<&str>)]) -> String {
+        pkgs.iter()
+            .map(|(name, version)| match version {
+                Some(branch) => sh_quote(&format!("{name}//{branch}")),
+                None => sh_quote(name),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+    /// one `snap` call per package, as a channel applies to all of them
+    fn snap_each(op: &str, pkgs: &[(&str, Option<&str>)]) -> String {
+        pkgs.iter()
+            .map(|(name, channel)| match channel {
+                Some(channel) => format!(
+                    "$SUDO snap {op} {} --channel={}",
+                    sh_quote(name),
+                    sh_quote(channel)
+                ),
+                None => format!("$SUDO snap {op} {}", sh_quote(name)),
+            })
+            .collect::<Vec<_>>()
+            .join(" && ")
+    }
+    pub fn install(
+        &self,
+        scope: Scope,
+        remote: Option<&str>,
+        classic: bool,
+        pkgs: &[(&str, Option<&str>)],
+    ) -> String {
+        match self {
+            Backend::Flatpak => format!(
+                "{ROOT_PRELUDE}{}flatpak install {} -y --noninteractive {}{}",
+                self.sudo(scope),
+                Self::scope(scope),
+                remote.map(|r| sh_quote(r) + " ").unwrap_or_default(),
+                Self::flatpak_refs(pkgs)
+            ),
+            Backend::Snap if classic => format!(
+                "{ROOT_PRELUDE}{}",
+                Self::snap_each("install --classic", pkgs)
+            ),
+            Backend::Snap => format!("{ROOT_PRELUDE}{}", Self::snap_each("install", pkgs)),
+        }
+    }
+    pub fn remove(&self, scope: Scope, pkgs: &[(&str, Option<&str>)]) -> String {
+        match self {
+            Backend::Flatpak => format!(
+                "{ROOT_PRELUDE}{}flatpak uninstall {} -y --noninteractive {}",
+                self.sudo(scope),
+                Self::scope(scope),
+                names(pkgs)
+            ),
+            Backend::Snap => format!("{ROOT_PRELUDE}$SUDO snap remove {}", names(pkgs)),
+        }
+    }
+    pub fn upgrade(&self, scope: Scope, pkgs: &[(&str, Option<&str>)]) -> String {
+        match self {
+            Backend::Flatpak => format!(
+                "{ROOT_PRELUDE}{}flatpak update {} -y --noninteractive {}",
+                self.sudo(scope),
+                Self::scope(scope),
+                Self::flatpak_refs(pkgs)
+            )
+            .trim_end()
+            .to_string(),
+            Backend::Snap if pkgs.is_empty() => format!("{ROOT_PRELUDE}$SUDO snap refresh"),
+            Backend::Snap => format!("{ROOT_PRELUDE}{}", Self::snap_each("refresh", pkgs)),
+        }
+    }
+    /// refreshes the metadata, snapd keeps its own so this only lists pending refreshes
+    pub fn update(&self, scope: Scope) -> String {
+        match self {
+            Backend::Flatpak => format!(
+                "{ROOT_PRELUDE}{}flatpak update {} -y --noninteractive --appstream",
+                self.sudo(scope),
+                Self::scope(scope)
+            ),
+            Backend::Snap => "snap refresh --list".to_string(),
+        }
+    }
+    /// prints the name of every installed application and runtime with what versions pin, the
+    /// flatpak branch or the snap channel tracked
+    pub fn query(&self, scope: Scope) -> String {
+        match self {
+            Backend::Flatpak => format!(
+                "flatpak list {} --columns=application,branch",
+                Self::scope(scope)
+            ),
+            Backend::Snap => "snap list 2>/dev/null | awk 'NR > 1 { print $1, $4 }'".to_string(),
+        }
+    }
+}
+
+/// flatpak remotes of `scope`, added with `--if-not-exists` and only deleted when present
+pub fn remote_add(scope: Scope, name: &str, url: &str) -> String {
+    format!(
+        "{ROOT_PRELUDE}{}flatpak remote-add {} --if-not-exists {} {}",
+        Backend::Flatpak.sudo(scope),
+        Backend::scope(scope),
+        sh_quote(name),
+        sh_quote(url)
+    )
+}
+pub fn remote_delete(scope: Scope, name: &str) -> String {
+    let sudo = Backend::Flatpak.sudo(scope);
+    let scope = Backend::scope(scope);
+    let name = sh_quote(name);
+    format!(
+        "{ROOT_PRELUDE}if flatpak remotes {scope} --columns=name | grep -qxF {name}; then {sudo}flatpak remote-delete {scope} --force {name}; fi"
+    )
+}
+fn remotes_cmd(scope: Scope) -> String {
+    format!(
+        "flatpak remotes {} --columns=name,url",
+        Backend::scope(scope)
+    )
+}
+
+async fn uid(ctx: &ContextWrapper, device: &str, backend: Backend, scope: Scope) -> Result<String> {
+    if backend == Backend::Snap || scope == Scope::System {
+        system_uid(ctx, device).await
+    } else {
+        user_uid(ctx, device).await
+    }
+}
+
+async fn privileged_uid(
+    ctx: &ContextWrapper,
+    device: &str,
+    backend: Backend,
+    opts: &PmOptions,
+) -> Result<String> {
+    let uid = uid(ctx, device, backend, opts.scope).await?;
+    if backend.sudo(opts.scope).is_empty() || passes(ctx, &uid, ROOT_CHECK, &opts.retry).await? {
+        return Ok(uid);
+    }
+    bail!(
+        "{uid} can't run {} for the whole system as it is neither root nor allowed to sudo without a password",
+        backend.name()
+    )
+}
+
+pub async fn change(
+    ctx: &ContextWrapper,
+    device: &str,
+    backend: Backend,
+    op: Op,
+    packages: &Packages,
+    opts: &PmOptions,
+) -> mlua::Result<bool> {
+    let resolved = resolve(ctx, device, &packages.names(), Some(backend), &opts.retry).await?;
+    let pkgs: Vec<(&str, Option<&str>)> = resolved
+        .iter()
+        .zip(&packages.0)
+        .map(|(name, p)| (name.as_str(), p.version.as_deref()))
+        .collect();
+    let (what, cmd) = match op {
+        Op::Install => (
+            "Install",
+            backend.install(opts.scope, opts.remote.as_deref(), opts.classic, &pkgs),
+        ),
+        Op::Remove => ("Remove", backend.remove(opts.scope, &pkgs)),
+        Op::Upgrade => ("Upgrade", backend.upgrade(opts.scope, &pkgs)),
+    };
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!(
+            "{what} on {device} with {}: {}",
+            backend.name(),
+            resolved.join(" ")
+        ))
+        .await;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    let what = format!("{what} with {}", backend.name());
+    if opts.confirm && !confirm(ctx, device, &what, None, &opts.retry).await? {
+        return Ok(false);
+    }
+    let uid = privileged_uid(ctx, device, backend, opts).await?;
+    run(ctx, &uid, &cmd, &opts.retry).await?;
+    Ok(true)
+}
+
+pub async fn update(
+    ctx: &ContextWrapper,
+    device: &str,
+    backend: Backend,
+    opts: &PmOptions,
+) -> mlua::Result<bool> {
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!("Update on {device} with {}", backend.name()))
+        .await;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    let what = format!("Update with {}", backend.name());
+    if opts.confirm && !confirm(ctx, device, &what, None, &opts.retry).await? {
+        return Ok(false);
+    }
+    // snapd refreshes its metadata on its own, listing refreshes needs no root
+    let uid = match backend {
+        Backend::Flatpak => privileged_uid(ctx, device, backend, opts).await?,
+        Backend::Snap => uid(ctx, device, backend, opts.scope).await?,
+    };
+    run(ctx, &uid, &backend.update(opts.scope), &opts.retry).await?;
+    Ok(true)
+}
+
+pub async fn query(
+    ctx: &ContextWrapper,
+    device: &str,
+    backend: Backend,
+    packages: &[&str],
+    opts: &PmOptions,
+) -> Result<Vec<(String, String)>> {
+    let uid = uid(ctx, device, backend, opts.scope).await?;
+    let output = run(ctx, &uid, &backend.query(opts.scope), &opts.retry).await?;
+    Ok(parse_query(&output)
+        .into_iter()
+        .filter(|(name, _)| packages.is_empty() || packages.contains(&name.as_str()))
+        .collect())
+}
+
+pub async fn remote(
+    ctx: &ContextWrapper,
+    device: &str,
+    name: &str,
+    url: Option<&str>,
+    opts: &PmOptions,
+) -> mlua::Result<bool> {
+    let (what, cmd) = match url {
+        Some(url) => (
+            format!("Add flatpak remote {name} ({url})"),
+            remote_add(opts.scope, name, url),
+        ),
+        None => (
+            format!("Remove flatpak remote {name}"),
+            remote_delete(opts.scope, name),
+        ),
+    };
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!("{what} on {device}"))
+        .await;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    if opts.confirm && !confirm(ctx, device, &what, None, &opts.retry).await? {
+        return Ok(false);
+    }
+    let uid = privileged_uid(ctx, device, Backend::Flatpak, opts).await?;
+    run(ctx, &uid, &cmd, &opts.retry).await?;
+    Ok(true)
+}
+
+pub async fn remotes(
+    ctx: &ContextWrapper,
+    device: &str,
+    opts: &PmOptions,
+) -> Result<Vec<(String, String)>> {
+    let uid = uid(ctx, device, Backend::Flatpak, opts.scope).await?;
+    let output = run(ctx, &uid, &remotes_cmd(opts.scope), &opts.retry).await?;
+    Ok(parse_query(&output))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backend, Scope, remote_delete};
+
+    #[test]
+    fn app_backend_commands() {
+        assert!(
+            Backend::Flatpak
+                .install(
+                    Scope::User,
+                    Some("flathub"),
+                    false,
+                    &[("org.gimp.GIMP", None), ("org.gnome.Platform", Some("45"))]
+                )
+                .ends_with("; flatpak install --user -y --noninteractive 'flathub' 'org.gimp.GIMP' 'org.gnome.Platform//45'")
+        );
+        assert!(
+            Backend::Snap
+                .install(
+                    Scope::User,
+                    None,
+                    true,
+                    &[("code", None), ("go", Some("1.22/stable"))]
+                )
+                .ends_with(
+                    "$SUDO snap install --classic 'code' && $SUDO snap install --classic 'go' --channel='1.22/stable'"
+                )
+        );
+        assert!(
+            Backend::Flatpak
+                .upgrade(Scope::System, &[])
+                .ends_with("; $SUDO flatpak update --system -y --noninteractive")
+        );
+        assert!(remote_delete(Scope::User, "flathub").ends_with(
+            "; if flatpak remotes --user --columns=name | grep -qxF 'flathub'; then flatpak remote-delete --user --force 'flathub'; fi"
+        ));
+    }
+}
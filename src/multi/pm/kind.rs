@@ -0,0 +1,602 @@
+use crate::util::sh_quote;
+use anyhow::{Result, anyhow};
+use dv_api::process::ScriptExecutor;
+
+pub const ROOT_PRELUDE: &str = r#"SUDO=; [ "$(id -u)" -eq 0 ] || SUDO="sudo -n"; "#;
+
+pub const ROOT_CHECK: &str = r#"[ "$(id -u)" -eq 0 ] || sudo -n true 2>/dev/null"#;
+
+pub const SUDO_CHECK: &str = r#"[ "$(id -u)" -eq 0 ] || command -v sudo >/dev/null 2>&1"#;
+
+pub const WHERE: &str = "where.exe winget scoop";
+
+const WINGET_FLAGS: &str = "--exact --silent --disable-interactivity --accept-package-agreements --accept-source-agreements";
+
+/// exit code of winget when no package matched
+const WINGET_NO_MATCH: i32 = 0x8A150014_u32 as i32;
+
+const APK_SPLIT: &str = r#"n = split($1, p, "-"); v = p[n-1] "-" p[n]; name = substr($1, 1, length($1) - length(v) - 1)"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PmKind {
+    Apt,
+    Dnf,
+    Yum,
+    Pacman,
+    Yay,
+    Paru,
+    Zypper,
+    Apk,
+    Brew,
+    Winget,
+    Scoop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Install,
+    Remove,
+    Upgrade,
+}
+
+fn packages(pkgs: &[&str]) -> String {
+    pkgs.iter()
+        .map(|p| sh_quote(p))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// double quotes work in both the `cmd` and PowerShell default shells of windows
+fn win_packages(pkgs: &[&str]) -> String {
+    pkgs.iter()
+        .map(|p| format!("\"{p}\""))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl PmKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "apt" => PmKind::Apt,
+            "dnf" => PmKind::Dnf,
+            "yum" => PmKind::Yum,
+            "pacman" => PmKind::Pacman,
+            "yay" => PmKind::Yay,
+            "paru" => PmKind::Paru,
+            "zypper" => PmKind::Zypper,
+            "apk" => PmKind::Apk,
+            "brew" => PmKind::Brew,
+            "winget" => PmKind::Winget,
+            "scoop" => PmKind::Scoop,
+            _ => return None,
+        })
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            PmKind::Apt => "apt",
+            PmKind::Dnf => "dnf",
+            PmKind::Yum => "yum",
+            PmKind::Pacman => "pacman",
+            PmKind::Yay => "yay",
+            PmKind::Paru => "paru",
+            PmKind::Zypper => "zypper",
+            PmKind::Apk => "apk",
+            PmKind::Brew => "brew",
+            PmKind::Winget => "winget",
+            PmKind::Scoop => "scoop",
+        }
+    }
+    fn windows(&self) -> bool {
+        matches!(self, PmKind::Winget | PmKind::Scoop)
+    }
+    pub fn executor(&self) -> Option<ScriptExecutor> {
+        if self.windows() {
+            None
+        } else {
+            Some(ScriptExecutor::Sh)
+        }
+    }
+    /// whether `code` only tells that a query or search matched nothing, or not everything
+    pub fn none_found(&self, code: i32) -> bool {
+        match self {
+            PmKind::Pacman | PmKind::Yay | PmKind::Paru | PmKind::Brew => code == 1,
+            PmKind::Winget => code == WINGET_NO_MATCH,
+            _ => false,
+        }
+    }
+    pub fn alias_keys(&self) -> &'static [&'static str] {
+        match self {
+            PmKind::Apt => &["apt"],
+            PmKind::Dnf => &["dnf", "yum"],
+            PmKind::Yum => &["yum", "dnf"],
+            PmKind::Pacman => &["pacman"],
+            PmKind::Yay => &["yay", "pacman"],
+            PmKind::Paru => &["paru", "pacman"],
+            PmKind::Zypper => &["zypper"],
+            PmKind::Apk => &["apk"],
+            PmKind::Brew => &["brew"],
+            PmKind::Winget => &["winget"],
+            PmKind::Scoop => &["scoop"],
+        }
+    }
+    pub fn pin(&self, name: &str, version: &str) -> Option<String> {
+        match self {
+            PmKind::Apt | PmKind::Apk => Some(format!("{name}={version}")),
+            PmKind::Dnf | PmKind::Yum | PmKind::Zypper => Some(format!("{name}-{version}")),
+            PmKind::Scoop => Some(format!("{name}@{version}")),
+            // `name@version` is a separate versioned formula, if there is one at all
+            PmKind::Pacman | PmKind::Yay | PmKind::Paru | PmKind::Brew | PmKind::Winget => None,
+        }
+    }
+    /// AUR helpers and brew refuse to run as root, the others need it to change anything;
+    /// windows managers elevate on their own
+    pub fn needs_root(&self) -> bool {
+        !matches!(
+            self,
+            PmKind::Yay | PmKind::Paru | PmKind::Brew | PmKind::Winget | PmKind::Scoop
+        )
+    }
+    fn sudo(&self) -> &'static str {
+        if self.needs_root() { "$SUDO " } else { "" }
+    }
+    pub fn privilege_check(&self) -> Option<&'static str> {
+        if self.windows() {
+            None
+        } else if self.needs_root() {
+            Some(ROOT_CHECK)
+        } else {
+            Some(r#"[ "$(id -u)" -ne 0 ]"#)
+        }
+    }
+    pub fn change(&self, op: Op, pkgs: &[&str]) -> String {
+        let all = pkgs.is_empty();
+        let name = self.name();
+        if self.windows() {
+            let cmd = match (self, op) {
+                (PmKind::Winget, Op::Install) => format!("winget install {WINGET_FLAGS}"),
+                (PmKind::Winget, Op::Remove) => {
+                    "winget uninstall --exact --silent --disable-interactivity".to_string()
+                }
+                (PmKind::Winget, Op::Upgrade) if all => format!(
+                    "winget upgrade --all {}",
+                    WINGET_FLAGS.trim_start_matches("--exact ")
+                ),
+                (PmKind::Winget, Op::Upgrade) => format!("winget upgrade {WINGET_FLAGS}"),
+                (_, Op::Install) => "scoop install".to_string(),
+                (_, Op::Remove) => "scoop uninstall".to_string(),
+                (_, Op::Upgrade) if all => "scoop update *".to_string(),
+                (_, Op::Upgrade) => "scoop update".to_string(),
+            };
+            return format!("{cmd} {}", win_packages(pkgs))
+                .trim_end()
+                .to_string();
+        }
+        let cmd = match (self, op) {
+            (PmKind::Apt, Op::Install) => {
+                "DEBIAN_FRONTEND=noninteractive apt-get install -y".into()
+            }
+            (PmKind::Apt, Op::Remove) => "DEBIAN_FRONTEND=noninteractive apt-get remove -y".into(),
+            (PmKind::Apt, Op::Upgrade) if all => {
+                "DEBIAN_FRONTEND=noninteractive apt-get upgrade -y".into()
+            }
+            (PmKind::Apt, Op::Upgrade) => {
+                "DEBIAN_FRONTEND=noninteractive apt-get install --only-upgrade -y".into()
+            }
+            (PmKind::Dnf | PmKind::Yum, Op::Install) => format!("{name} install -y"),
+            (PmKind::Dnf | PmKind::Yum, Op::Remove) => format!("{name} remove -y"),
+            (PmKind::Dnf, Op::Upgrade) => "dnf upgrade -y".into(),
+            (PmKind::Yum, Op::Upgrade) => "yum update -y".into(),
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Install) => {
+                format!("{name} -S --needed --noconfirm")
+            }
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Remove) => {
+                format!("{name} -Rns --noconfirm")
+            }
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Upgrade) if all => {
+                format!("{name} -Syu --noconfirm")
+            }
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Upgrade) => {
+                format!("{name} -S --noconfirm")
+            }
+            (PmKind::Zypper, Op::Install) => "zypper --non-interactive install".into(),
+            (PmKind::Zypper, Op::Remove) => "zypper --non-interactive remove".into(),
+            (PmKind::Zypper, Op::Upgrade) => "zypper --non-interactive update".into(),
+            (PmKind::Apk, Op::Install) => "apk add".into(),
+            (PmKind::Apk, Op::Remove) => "apk del".into(),
+            (PmKind::Apk, Op::Upgrade) => "apk upgrade".into(),
+            (PmKind::Brew, Op::Install) => "brew install".into(),
+            (PmKind::Brew, Op::Remove) => "brew uninstall".into(),
+            (PmKind::Brew, Op::Upgrade) => "brew upgrade".into(),
+            (PmKind::Winget | PmKind::Scoop, _) => unreachable!("handled above"),
+        };
+        let cmd = format!("{ROOT_PRELUDE}{}{cmd} {}", self.sudo(), packages(pkgs));
+        cmd.trim_end().to_string()
+    }
+    pub fn update(&self) -> String {
+        let cmd = match self {
+            PmKind::Apt => "apt-get update",
+            PmKind::Dnf => "dnf makecache",
+            PmKind::Yum => "yum makecache",
+            PmKind::Pacman => "pacman -Sy --noconfirm",
+            PmKind::Yay => "yay -Sy --noconfirm",
+            PmKind::Paru => "paru -Sy --noconfirm",
+            PmKind::Zypper => "zypper --non-interactive refresh",
+            PmKind::Apk => "apk update",
+            PmKind::Brew => "brew update",
+            PmKind::Winget => return "winget source update".to_string(),
+            PmKind::Scoop => return "scoop update".to_string(),
+        };
+        format!("{ROOT_PRELUDE}{}{cmd}", self.sudo())
+    }
+    /// prints the transaction `op` would make without changing anything, `None` when the
+    /// manager can't simulate it; only zypper needs root for that
+    pub fn preview(&self, op: Op, pkgs: &[&str]) -> Option<String> {
+        let pkgs = packages(pkgs);
+        let cmd = match (self, op) {
+            (PmKind::Apt, Op::Install) => "apt-get install -s",
+            (PmKind::Apt, Op::Remove) => "apt-get remove -s",
+            (PmKind::Apt, Op::Upgrade) if pkgs.is_empty() => "apt-get upgrade -s",
+            (PmKind::Apt, Op::Upgrade) => "apt-get install --only-upgrade -s",
+            (PmKind::Dnf, Op::Install) => "dnf install --assumeno",
+            (PmKind::Dnf, Op::Remove) => "dnf remove --assumeno",
+            (PmKind::Dnf, Op::Upgrade) => "dnf upgrade --assumeno",
+            (PmKind::Yum, Op::Install) => "yum install --assumeno",
+            (PmKind::Yum, Op::Remove) => "yum remove --assumeno",
+            (PmKind::Yum, Op::Upgrade) => "yum update --assumeno",
+            // AUR helpers hand repository transactions over to pacman
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Install) => "pacman -S --print",
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Remove) => "pacman -Rns --print",
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Upgrade) if pkgs.is_empty() => {
+                "pacman -Su --print"
+            }
+            (PmKind::Pacman | PmKind::Yay | PmKind::Paru, Op::Upgrade) => "pacman -S --print",
+            (PmKind::Zypper, Op::Install) => "zypper --non-interactive install --dry-run",
+            (PmKind::Zypper, Op::Remove) => "zypper --non-interactive remove --dry-run",
+            (PmKind::Zypper, Op::Upgrade) => "zypper --non-interactive update --dry-run",
+            (PmKind::Apk, Op::Install) => "apk add --simulate",
+            (PmKind::Apk, Op::Remove) => "apk del --simulate",
+            (PmKind::Apk, Op::Upgrade) => "apk upgrade --simulate",
+            (PmKind::Brew, Op::Install) => "brew install --dry-run",
+            (PmKind::Brew, Op::Remove) => return None,
+            (PmKind::Brew, Op::Upgrade) => "brew upgrade --dry-run",
+            (PmKind::Winget | PmKind::Scoop, _) => return None,
+        };
+        let cmd = format!("{cmd} {pkgs}");
+        let cmd = cmd.trim_end();
+        Some(match self {
+            PmKind::Zypper => format!("{ROOT_PRELUDE}$SUDO {cmd} 2>&1"),
+            _ => format!("{cmd} 2>&1"),
+        })
+    }
+    /// prints `name version` for each installed package of `pkgs`, or of all when empty; the
+    /// windows managers print a table of all of them, see [`PmKind::parse_query`]
+    pub fn query(&self, pkgs: &[&str]) -> String {
+        match self {
+            PmKind::Winget => {
+                return "winget list --accept-source-agreements --disable-interactivity"
+                    .to_string();
+            }
+            PmKind::Scoop => return "scoop list".to_string(),
+            _ => {}
+        }
+        let pkgs = packages(pkgs);
+        match self {
+            PmKind::Apt => format!(
+                r#"dpkg-query -W -f='${{db:Status-Abbrev}} ${{Package}} ${{Version}}\n' {pkgs} 2>/dev/null | awk '$1 == "ii" {{ print $2, $3 }}'"#
+            ),
+            PmKind::Dnf | PmKind::Yum | PmKind::Zypper => {
+                let all = if pkgs.is_empty() { "-a" } else { "" };
+                format!(
+                    r#"rpm -q {all} --qf '%{{NAME}} %{{VERSION}}-%{{RELEASE}}\n' {pkgs} 2>/dev/null | awk 'NF == 2'"#
+                )
+            }
+            PmKind::Pacman | PmKind::Yay | PmKind::Paru => {
+                format!("pacman -Q {pkgs} 2>/dev/null")
+            }
+            PmKind::Apk => format!(
+                r#"apk list -I {pkgs} 2>/dev/null | awk '{{ {APK_SPLIT}; print name, v }}'"#
+            ),
+            PmKind::Brew => format!("brew list --versions {pkgs} 2>/dev/null"),
+            PmKind::Winget | PmKind::Scoop => unreachable!("listed above"),
+        }
+    }
+    pub fn search(&self, term: &str) -> String {
+        match self {
+            PmKind::Winget => {
+                return format!(
+                    "winget search --accept-source-agreements --disable-interactivity {}",
+                    win_packages(&[term])
+                );
+            }
+            PmKind::Scoop => return format!("scoop search {}", win_packages(&[term])),
+            _ => {}
+        }
+        let term = sh_quote(term);
+        match self {
+            PmKind::Apt => format!(r#"apt-cache search -- {term} | sed 's/ - /\t/'"#),
+            PmKind::Dnf | PmKind::Yum => format!(
+                r#"{} search -q -- {term} 2>/dev/null | awk -F ' : ' 'NF == 2 {{ sub(/\.[^.]*$/, "", $1); gsub(/ +$/, "", $1); print $1 "\t" $2 }}'"#,
+                self.name()
+            ),
+            PmKind::Pacman | PmKind::Yay | PmKind::Paru => format!(
+                r#"{} -Ss -- {term} | awk '/^[^ ]/ {{ n = split($1, p, "/"); name = p[n]; next }} {{ sub(/^ +/, ""); print name "\t" $0 }}'"#,
+                self.name()
+            ),
+            PmKind::Zypper => format!(
+                r#"zypper --non-interactive --quiet search -- {term} | awk -F '|' 'NR > 2 {{ gsub(/^ +| +$/, "", $2); gsub(/^ +| +$/, "", $3); print $2 "\t" $3 }}'"#
+            ),
+            PmKind::Apk => format!(
+                r#"apk search -v -d -- {term} | awk -F ' - ' '{{ {APK_SPLIT}; print name "\t" $2 }}'"#
+            ),
+            // awk rather than `grep -v`, which fails when nothing is left
+            PmKind::Brew => format!("brew search -- {term} 2>/dev/null | awk '!/^==/'"),
+            PmKind::Winget | PmKind::Scoop => unreachable!("searched above"),
+        }
+    }
+    pub fn parse_query(&self, output: &str, pkgs: &[&str]) -> Vec<(String, String)> {
+        let key = match self {
+            PmKind::Winget => "Id",
+            PmKind::Scoop => "Name",
+            _ => return parse_query(output),
+        };
+        parse_table(output, key, Some("Version"))
+            .into_iter()
+            .filter_map(|(name, version)| {
+                if pkgs.is_empty() {
+                    return Some((name, version));
+                }
+                // winget ids are case-insensitive
+                let wanted = pkgs.iter().find(|p| p.eq_ignore_ascii_case(&name))?;
+                Some((wanted.to_string(), version))
+            })
+            .collect()
+    }
+    pub fn parse_search(&self, output: &str) -> Vec<(String, String)> {
+        match self {
+            PmKind::Winget => parse_table(output, "Id", Some("Name")),
+            PmKind::Scoop => parse_table(output, "Name", None),
+            _ => parse_search(output),
+        }
+    }
+    pub fn from_where(output: &str) -> Result<Self> {
+        output
+            .lines()
+            .find_map(|line| {
+                let name = line.trim().rsplit(['\\', '/']).next()?;
+                let stem = name.split('.').next()?;
+                PmKind::from_name(&stem.to_lowercase())
+            })
+            .ok_or_else(|| anyhow!("Neither winget nor scoop found"))
+    }
+}
+
+/// parses `name version` lines, the first version wins for brew's multiple ones
+pub fn parse_query(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut it = line.split_whitespace();
+            Some((it.next()?.to_string(), it.next()?.to_string()))
+        })
+        .collect()
+}
+
+/// parses the `key` and `value` columns of a table as printed by winget and scoop: a header
+/// line, a line of dashes, then rows whose columns start where their header does
+fn parse_table(output: &str, key: &str, value: Option<&str>) -> Vec<(String, String)> {
+    // progress output is overwritten through carriage returns
+    let lines: Vec<&str> = output
+        .lines()
+        .map(|line| line.rsplit('\r').next().unwrap_or(line))
+        .collect();
+    let Some(dashes) = lines.iter().position(|line| {
+        let line = line.trim();
+        line.starts_with("--") && line.chars().all(|c| c == '-' || c == ' ')
+    }) else {
+        return Vec::new();
+    };
+    let Some(header) = dashes.checked_sub(1).map(|i| lines[i]) else {
+        return Vec::new();
+    };
+    let header: Vec<char> = header.chars().collect();
+    // start of each column, at the first letter of its header
+    let starts: Vec<usize> = (0..header.len())
+        .filter(|&i| !header[i].is_whitespace() && (i == 0 || header[i - 1].is_whitespace()))
+        .collect();
+    let column = |name: &str| {
+        starts.iter().position(|&start| {
+            header[start..]
+                .iter()
+                .take_while(|c| !c.is_whitespace())
+                .collect::<String>()
+                == name
+        })
+    };
+    let Some(key) = column(key) else {
+        return Vec::new();
+    };
+    let value = value.and_then(column);
+    let cell = |row: &[char], i: usize| {
+        let start = starts[i].min(row.len());
+        let end = starts
+            .get(i + 1)
+            .map_or(row.len(), |&end| end.min(row.len()));
+        row[start..end]
+            .iter()
+            .collect::<String>()
+            .trim()
+            .to_string()
+    };
+    lines[dashes + 1..]
+        .iter()
+        .map(|line| line.chars().collect::<Vec<_>>())
+        .filter_map(|row| {
+            let name = cell(&row, key);
+            if name.is_empty() {
+                return None;
+            }
+            Some((name, value.map(|v| cell(&row, v)).unwrap_or_default()))
+        })
+        .collect()
+}
+
+pub fn parse_search(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match line.split_once('\t') {
+            Some((name, description)) => (name.trim().to_string(), description.trim().to_string()),
+            None => (line.trim().to_string(), String::new()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Op, PmKind, parse_query, parse_search};
+
+    #[test]
+    fn pm_kind_commands() {
+        assert_eq!(
+            PmKind::Apt.change(Op::Remove, &["fd-find", "it's"]),
+            r#"SUDO=; [ "$(id -u)" -eq 0 ] || SUDO="sudo -n"; $SUDO DEBIAN_FRONTEND=noninteractive apt-get remove -y 'fd-find' 'it'\''s'"#
+        );
+        assert!(
+            PmKind::Brew
+                .change(Op::Remove, &["fd"])
+                .ends_with("; brew uninstall 'fd'")
+        );
+        assert!(
+            PmKind::Paru
+                .change(Op::Upgrade, &[])
+                .ends_with("; paru -Syu --noconfirm")
+        );
+        assert!(
+            PmKind::Apt
+                .change(Op::Install, &["git"])
+                .ends_with("$SUDO DEBIAN_FRONTEND=noninteractive apt-get install -y 'git'")
+        );
+        assert!(PmKind::Apk.update().ends_with("$SUDO apk update"));
+        assert_eq!(
+            PmKind::Winget.change(Op::Upgrade, &[]),
+            "winget upgrade --all --silent --disable-interactivity --accept-package-agreements --accept-source-agreements"
+        );
+        assert_eq!(
+            PmKind::Scoop.change(Op::Install, &["git"]),
+            r#"scoop install "git""#
+        );
+        assert_eq!(PmKind::Pacman.query(&["fd"]), "pacman -Q 'fd' 2>/dev/null");
+        assert!(PmKind::Dnf.query(&[]).starts_with("rpm -q -a "));
+        assert_eq!(PmKind::Dnf.pin("git", "2.43"), Some("git-2.43".to_string()));
+        assert_eq!(PmKind::Pacman.pin("git", "2.43"), None);
+        assert_eq!(PmKind::Brew.pin("git", "2.43"), None);
+        assert!(
+            PmKind::Apt
+                .privilege_check()
+                .expect("apt runs in sh")
+                .contains("sudo -n true")
+        );
+        assert_eq!(PmKind::Winget.privilege_check(), None);
+        assert!(!PmKind::Brew.needs_root());
+        assert_eq!(
+            PmKind::Apt
+                .preview(Op::Upgrade, &[])
+                .expect("apt can simulate"),
+            "apt-get upgrade -s 2>&1"
+        );
+        assert_eq!(
+            PmKind::Dnf
+                .preview(Op::Remove, &["git"])
+                .expect("dnf can simulate"),
+            "dnf remove --assumeno 'git' 2>&1"
+        );
+        assert!(
+            PmKind::Zypper
+                .preview(Op::Install, &["git"])
+                .expect("zypper can simulate")
+                .ends_with("$SUDO zypper --non-interactive install --dry-run 'git' 2>&1")
+        );
+        assert_eq!(PmKind::Brew.preview(Op::Remove, &["fd"]), None);
+        assert_eq!(PmKind::from_name("paru"), Some(PmKind::Paru));
+        assert_eq!(PmKind::from_name("winget"), Some(PmKind::Winget));
+        assert_eq!(PmKind::from_name("choco"), None);
+        assert_eq!(
+            PmKind::Scoop.change(Op::Remove, &["git"]),
+            r#"scoop uninstall "git""#
+        );
+        assert_eq!(PmKind::Winget.preview(Op::Install, &["Git.Git"]), None);
+        assert!(PmKind::Brew.search("fd").ends_with("| awk '!/^==/'"));
+        assert_eq!(
+            PmKind::from_where("C:\\Users\\km0e\\scoop\\shims\\scoop.cmd\r\n")
+                .expect("scoop is there"),
+            PmKind::Scoop
+        );
+        assert!(PmKind::from_where("").is_err());
+    }
+
+    #[test]
+    fn pm_kind_missing_package() {
+        // `pacman -Q` and `brew list --versions` exit 1 when a package isn't installed, but
+        // still print the ones that are
+        assert!(PmKind::Pacman.none_found(1));
+        assert!(PmKind::Brew.none_found(1));
+        assert!(PmKind::Winget.none_found(0x8A150014_u32 as i32));
+        assert!(!PmKind::Pacman.none_found(2));
+        assert!(!PmKind::Apt.none_found(1));
+        assert_eq!(PmKind::Pacman.parse_query("", &["nope"]), Vec::new());
+        assert_eq!(
+            PmKind::Brew.parse_query("fd 10.2.0\n", &["fd", "nope"]),
+            vec![("fd".to_string(), "10.2.0".to_string())]
+        );
+    }
+
+    #[test]
+    fn pm_kind_parse_table() {
+        let winget = "   - \r   \\ \rName   Id            Version   Available Source\n\
+                      ---------------------------------------------------\n\
+                      Git    Git.Git       2.43.0    2.44.0    winget\n\
+                      7-Zip  7zip.7zip     23.01               winget\n";
+        assert_eq!(
+            PmKind::Winget.parse_query(winget, &["git.git", "nope"]),
+            vec![("git.git".to_string(), "2.43.0".to_string())]
+        );
+        assert_eq!(PmKind::Winget.parse_query(winget, &[]).len(), 2);
+        assert_eq!(
+            PmKind::Winget.parse_search(winget)[1],
+            ("7zip.7zip".to_string(), "7-Zip".to_string())
+        );
+        let scoop = "Installed apps:\n\n\
+                     Name Version Source Updated             Info\n\
+                     ---- ------- ------ -------             ----\n\
+                     7zip 23.01   main   2024-01-01 10:00:00\n";
+        assert_eq!(
+            PmKind::Scoop.parse_query(scoop, &[]),
+            vec![("7zip".to_string(), "23.01".to_string())]
+        );
+        assert_eq!(
+            PmKind::Scoop.parse_query("No apps found.\n", &[]),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn pm_kind_parse() {
+        assert_eq!(
+            parse_query("fd 10.2.0-1\nripgrep 14.1.1 14.1.0\n\n"),
+            vec![
+                ("fd".to_string(), "10.2.0-1".to_string()),
+                ("ripgrep".to_string(), "14.1.1".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_search("fd-find\tSimple, fast alternative to find\nfd\n"),
+            vec![
+                (
+                    "fd-find".to_string(),
+                    "Simple, fast alternative to find".to_string()
+                ),
+                ("fd".to_string(), String::new()),
+            ]
+        );
+    }
+}
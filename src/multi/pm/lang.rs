@@ -0,0 +1,276 @@
+use super::kind::parse_query;
+use super::{Packages, PmOptions, confirm, report_table, run, user_uid};
+use crate::multi::dev::*;
+use crate::multi::retry::RetryOptions;
+use crate::util::sh_quote;
+
+/// where user installs land, as non-login shells often miss them
+const PATH_PRELUDE: &str =
+    r#"PATH="$HOME/.cargo/bin:$HOME/.local/bin:$HOME/go/bin:/usr/local/go/bin:$PATH"; "#;
+
+const GO_BIN: &str = r#"bin="$(go env GOBIN)"; [ -n "$bin" ] || bin="$(go env GOPATH)/bin"; "#;
+
+/// global npm installs go to `~/.local` unless the configured prefix is writable, e.g. one of nvm
+const NPM_PREFIX: &str = r#"[ -w "$(npm prefix -g 2>/dev/null)" ] || { NPM_CONFIG_PREFIX="$HOME/.local"; export NPM_CONFIG_PREFIX; }; "#;
+
+const PIP_MANAGED: &str = r#"if [ -z "$PIP_BREAK_SYSTEM_PACKAGES" ] && python3 -c 'import os, sys, sysconfig; sys.exit(not os.path.exists(os.path.join(sysconfig.get_path("stdlib"), "EXTERNALLY-MANAGED")))' 2>/dev/null; then echo "python3 is externally managed (PEP 668), install apps with pm:pipx instead" >&2; exit 1; fi; "#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LangKind {
+    Cargo,
+    Pip,
+    Pipx,
+    Npm,
+    Go,
+}
+
+fn packages(pkgs: &[String]) -> String {
+    pkgs.iter()
+        .map(|p| sh_quote(p))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// the binary `go install` builds for a package path, skipping a major version suffix
+fn go_bin(path: &str) -> &str {
+    let path = path.split('@').next().unwrap_or(path);
+    let mut parts = path.rsplit('/');
+    let last = parts.next().unwrap_or(path);
+    let is_major =
+        last.len() > 1 && last.starts_with('v') && last[1..].chars().all(|c| c.is_ascii_digit());
+    match parts.next() {
+        Some(prev) if is_major => prev,
+        _ => last,
+    }
+}
+
+impl LangKind {
+    pub const ALL: [LangKind; 5] = [
+        LangKind::Cargo,
+        LangKind::Pip,
+        LangKind::Pipx,
+        LangKind::Npm,
+        LangKind::Go,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            LangKind::Cargo => "cargo",
+            LangKind::Pip => "pip",
+            LangKind::Pipx => "pipx",
+            LangKind::Npm => "npm",
+            LangKind::Go => "go",
+        }
+    }
+    pub fn spec(&self, name: &str, version: Option<&str>) -> String {
+        match (self, version) {
+            (LangKind::Cargo | LangKind::Npm | LangKind::Go, Some(v)) => format!("{name}@{v}"),
+            (LangKind::Pip | LangKind::Pipx, Some(v)) => format!("{name}=={v}"),
+            // go install needs a version outside of a module
+            (LangKind::Go, None) => format!("{name}@latest"),
+            (_, None) => name.to_string(),
+        }
+    }
+    pub fn install(&self, specs: &[String]) -> String {
+        let pkgs = packages(specs);
+        let cmd = match self {
+            LangKind::Cargo => format!("cargo install {pkgs}"),
+            LangKind::Pip => format!("{PIP_MANAGED}python3 -m pip install --user {pkgs}"),
+            LangKind::Pipx => format!("pipx install {pkgs}"),
+            LangKind::Npm => format!("{NPM_PREFIX}npm install -g {pkgs}"),
+            LangKind::Go => format!(r#"for p in {pkgs}; do go install "$p" || exit 1; done"#),
+        };
+        format!("{PATH_PRELUDE}{cmd}")
+    }
+    pub fn remove(&self, names: &[String]) -> String {
+        let cmd = match self {
+            LangKind::Cargo => format!("cargo uninstall {}", packages(names)),
+            LangKind::Pip => format!(
+                "{PIP_MANAGED}python3 -m pip uninstall -y {}",
+                packages(names)
+            ),
+            LangKind::Pipx => format!(
+                r#"for p in {}; do pipx uninstall "$p" || exit 1; done"#,
+                packages(names)
+            ),
+            LangKind::Npm => format!("{NPM_PREFIX}npm uninstall -g {}", packages(names)),
+            LangKind::Go => {
+                let bins = names
+                    .iter()
+                    .map(|n| format!(r#""$bin"/{}"#, sh_quote(go_bin(n))))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{GO_BIN}rm -f {bins}")
+            }
+        };
+        format!("{PATH_PRELUDE}{cmd}")
+    }
+    pub fn query(&self) -> String {
+        let cmd = match self {
+            LangKind::Cargo => {
+                r#"cargo install --list | awk '/^[^ ]/ { v = $2; sub(/^v/, "", v); sub(/:$/, "", v); print $1, v }'"#
+            }
+            LangKind::Pip => {
+                "python3 -m pip list --user --format=freeze 2>/dev/null | sed 's/==/ /'"
+            }
+            LangKind::Pipx => "pipx list --short 2>/dev/null",
+            LangKind::Npm => {
+                return format!(
+                    r#"{PATH_PRELUDE}{NPM_PREFIX}npm ls -g --depth=0 --parseable --long 2>/dev/null | sed -nE 's/^[^:]*:(.+)@([^@:]+)(:.*)?$/\1 \2/p'"#
+                );
+            }
+            LangKind::Go => {
+                return format!(
+                    r#"{PATH_PRELUDE}{GO_BIN}go version -m "$bin"/* 2>/dev/null | awk '$1 == "path" {{ p = $2 }} $1 == "mod" {{ print p, $3 }}'"#
+                );
+            }
+        };
+        format!("{PATH_PRELUDE}{cmd}")
+    }
+}
+
+pub struct LangPm {
+    ctx: ContextWrapper,
+    device: String,
+    uid: String,
+    kind: LangKind,
+}
+
+impl LangPm {
+    pub async fn new(ctx: ContextWrapper, device: String, kind: LangKind) -> Result<Self> {
+        let uid = user_uid(&ctx, &device).await?;
+        Ok(Self {
+            ctx,
+            device,
+            uid,
+            kind,
+        })
+    }
+    async fn installed(&self, retry: &RetryOptions) -> Result<Vec<(String, String)>> {
+        let output = run(&self.ctx, &self.uid, &self.kind.query(), retry).await?;
+        Ok(parse_query(&output))
+    }
+    async fn change(
+        &self,
+        lua: &mlua::Lua,
+        what: &str,
+        cmd: String,
+        packages: &Packages,
+        present: bool,
+        opts: &PmOptions,
+    ) -> mlua::Result<mlua::Table> {
+        let names: Vec<String> = packages.0.iter().map(|p| p.name.clone()).collect();
+        self.ctx
+            .ctx()
+            .await
+            .interactor
+            .log(format!(
+                "{what} on {} with {}: {}",
+                self.uid,
+                self.kind.name(),
+                names.join(" ")
+            ))
+            .await;
+        if self.ctx.dry_run {
+            return report_table(lua, packages, &names, None, present, true);
+        }
+        let what = format!("{what} with {}", self.kind.name());
+        if opts.confirm && !confirm(&self.ctx, &self.device, &what, None, &opts.retry).await? {
+            return report_table(lua, packages, &names, None, present, false);
+        }
+        run(&self.ctx, &self.uid, &cmd, &opts.retry).await?;
+        let installed = self.installed(&opts.retry).await?;
+        report_table(lua, packages, &names, Some(&installed), present, true)
+    }
+}
+
+impl UserData for LangPm {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "install",
+            |lua, this, (packages, opts): (Packages, Option<PmOptions>)| async move {
+                let specs: Vec<String> = packages
+                    .0
+                    .iter()
+                    .map(|p| this.kind.spec(&p.name, p.version.as_deref()))
+                    .collect();
+                let cmd = this.kind.install(&specs);
+                let opts = opts.unwrap_or_default();
+                this.change(&lua, "Install", cmd, &packages, true, &opts)
+                    .await
+            },
+        );
+        methods.add_async_method(
+            "remove",
+            |lua, this, (packages, opts): (Packages, Option<PmOptions>)| async move {
+                let names: Vec<String> = packages.0.iter().map(|p| p.name.clone()).collect();
+                let cmd = this.kind.remove(&names);
+                let opts = opts.unwrap_or_default();
+                this.change(&lua, "Remove", cmd, &packages, false, &opts)
+                    .await
+            },
+        );
+        methods.add_async_method(
+            "is_installed",
+            |_, this, (package, opts): (String, Option<PmOptions>)| async move {
+                let installed = this.installed(&opts.unwrap_or_default().retry).await?;
+                Ok(installed.iter().any(|(n, _)| *n == package))
+            },
+        );
+        methods.add_async_method(
+            "installed_version",
+            |_, this, (package, opts): (String, Option<PmOptions>)| async move {
+                let installed = this.installed(&opts.unwrap_or_default().retry).await?;
+                Ok(installed
+                    .into_iter()
+                    .find(|(n, _)| *n == package)
+                    .map(|(_, version)| version))
+            },
+        );
+        methods.add_async_method(
+            "list_installed",
+            |lua, this, opts: Option<PmOptions>| async move {
+                lua.create_table_from(this.installed(&opts.unwrap_or_default().retry).await?)
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LangKind, go_bin};
+
+    #[test]
+    fn lang_kind_commands() {
+        assert_eq!(
+            LangKind::Cargo.spec("ripgrep", Some("14.1.0")),
+            "ripgrep@14.1.0"
+        );
+        assert_eq!(LangKind::Pipx.spec("black", Some("24.1")), "black==24.1");
+        assert_eq!(
+            LangKind::Go.spec("golang.org/x/tools/gopls", None),
+            "golang.org/x/tools/gopls@latest"
+        );
+        assert!(
+            LangKind::Npm
+                .install(&["typescript@5.4".to_string()])
+                .ends_with("; export NPM_CONFIG_PREFIX; }; npm install -g 'typescript@5.4'")
+        );
+        assert!(
+            LangKind::Npm
+                .query()
+                .contains(r#"NPM_CONFIG_PREFIX="$HOME/.local""#)
+        );
+        assert!(
+            LangKind::Pip
+                .install(&["black".to_string()])
+                .contains("EXTERNALLY-MANAGED")
+        );
+        assert!(
+            LangKind::Go
+                .remove(&["github.com/junegunn/fzf/v2".to_string()])
+                .ends_with(r#"rm -f "$bin"/'fzf'"#)
+        );
+        assert_eq!(go_bin("golang.org/x/tools/gopls@v0.15.0"), "gopls");
+        assert_eq!(go_bin("gopls"), "gopls");
+    }
+}
@@ -0,0 +1,109 @@
+use crate::util::conversion_error;
+use mlua::{FromLua, LuaSerdeExt, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    pub name: String,
+    pub version: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum Spec {
+    Name(String),
+    Pinned {
+        name: String,
+        version: Option<String>,
+    },
+}
+
+/// Packages given from lua, as `"fd ripgrep"` or `{"fd", {name = "ripgrep", version = "14.1"}}`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Packages(pub Vec<Package>);
+
+impl Packages {
+    pub fn from_names(names: &[String]) -> Self {
+        Self(
+            names
+                .iter()
+                .map(|name| Package {
+                    name: name.clone(),
+                    version: None,
+                })
+                .collect(),
+        )
+    }
+    pub fn names(&self) -> Vec<&str> {
+        self.0.iter().map(|p| p.name.as_str()).collect()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromLua for Packages {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            Value::String(s) => Ok(Self::from_names(
+                &s.to_str()?
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>(),
+            )),
+            Value::Table(_) => {
+                let specs: Vec<Spec> = lua.from_value(value)?;
+                Ok(Self(
+                    specs
+                        .into_iter()
+                        .map(|spec| match spec {
+                            Spec::Name(name) => Package {
+                                name,
+                                version: None,
+                            },
+                            Spec::Pinned { name, version } => Package { name, version },
+                        })
+                        .collect(),
+                ))
+            }
+            _ => Err(conversion_error(
+                value.type_name(),
+                "Packages",
+                Some("expected a string or a list of packages"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Package, Packages};
+    use mlua::FromLua;
+
+    fn packages(s: &str) -> Packages {
+        let lua = mlua::Lua::new();
+        let val = lua.load(s).eval::<mlua::Value>().expect("Failed to load");
+        Packages::from_lua(val, &lua).expect("Failed to deserialize")
+    }
+
+    #[test]
+    fn packages_serde() {
+        assert_eq!(packages("'fd  ripgrep'").names(), vec!["fd", "ripgrep"]);
+        assert_eq!(
+            packages("{'fd', {name = 'ripgrep', version = '14.1'}, {name = 'git'}}").0,
+            vec![
+                Package {
+                    name: "fd".to_string(),
+                    version: None,
+                },
+                Package {
+                    name: "ripgrep".to_string(),
+                    version: Some("14.1".to_string()),
+                },
+                Package {
+                    name: "git".to_string(),
+                    version: None,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use super::kind::{PmKind, ROOT_CHECK, ROOT_PRELUDE};
+use super::{passes, run, target};
+use crate::multi::dev::*;
+use crate::multi::retry::RetryOptions;
+use crate::util::sh_quote;
+use anyhow::bail;
+use mlua::{FromLua, LuaSerdeExt, Value};
+
+const CHANGED: &str = "changed";
+
+#[derive(serde::Deserialize, Debug, Default, PartialEq)]
+pub struct RepoSpec {
+    pub name: String,
+    pub url: String,
+    pub key: Option<String>,
+    pub suite: Option<String>,
+    #[serde(default = "default_components")]
+    pub components: String,
+    #[serde(flatten)]
+    pub retry: RetryOptions,
+}
+
+fn default_components() -> String {
+    "main".to_string()
+}
+
+impl FromLua for RepoSpec {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        lua.from_value(value)
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid {
+        bail!("Invalid repository name {name:?}");
+    }
+    Ok(())
+}
+
+fn write_file(path: &str, content: &str) -> String {
+    let path = sh_quote(path);
+    format!(
+        r#"if [ "$(cat {path} 2>/dev/null)" != {content} ]; then printf '%s\n' {content} | $SUDO tee {path} >/dev/null && echo {CHANGED}; fi"#
+    )
+}
+
+/// downloads `url` to `path` as root, nothing is written unless the whole download succeeds
+fn download(url: &str, path: &str) -> String {
+    format!(
+        r#"tmp="$(mktemp)" && {{ curl -fsSL -o "$tmp" {} && $SUDO install -D -m 0644 "$tmp" {}; st=$?; rm -f "$tmp"; [ "$st" -eq 0 ]; }}"#,
+        sh_quote(url),
+        sh_quote(path)
+    )
+}
+
+/// the first line of the base64 body of the armored key at `path`, which rpm keeps in the
+/// description of the `gpg-pubkey` it imports
+fn rpm_key_line(path: &str) -> String {
+    format!(
+        r#"line="$(awk 'NF && !/^-----/ && !/:/ {{ print; exit }}' {})""#,
+        sh_quote(path)
+    )
+}
+
+fn pacman_without(header: &str) -> String {
+    format!(
+        r#"awk -v s={header} '$0 == s {{ skip = 1; next }} /^\[/ {{ skip = 0 }} !skip' {PACMAN_CONF}"#
+    )
+}
+
+fn remove_files(paths: &[String]) -> String {
+    paths
+        .iter()
+        .map(|p| {
+            let p = sh_quote(p);
+            format!("if [ -e {p} ]; then $SUDO rm -f {p} && echo {CHANGED}; fi")
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn apt_key(name: &str) -> String {
+    format!("/etc/apt/keyrings/{name}.asc")
+}
+fn apt_list(name: &str) -> String {
+    format!("/etc/apt/sources.list.d/{name}.list")
+}
+fn dnf_repo(name: &str) -> String {
+    format!("/etc/yum.repos.d/{name}.repo")
+}
+fn rpm_key(name: &str) -> String {
+    format!("/etc/pki/rpm-gpg/RPM-GPG-KEY-{name}")
+}
+const PACMAN_CONF: &str = "/etc/pacman.conf";
+
+pub fn add(kind: PmKind, spec: &RepoSpec) -> Result<String> {
+    check_name(&spec.name)?;
+    let name = &spec.name;
+    let script = match kind {
+        PmKind::Apt => {
+            let key = apt_key(name);
+            let mut script = String::new();
+            let signed_by = match &spec.key {
+                Some(url) => {
+                    script += &format!(
+                        "if [ ! -s {} ]; then {} && echo {CHANGED}; fi && ",
+                        sh_quote(&key),
+                        download(url, &key)
+                    );
+                    format!(" [signed-by={key}]")
+                }
+                None => String::new(),
+            };
+            // the codename is only known on the device
+            let suite = match &spec.suite {
+                Some(suite) => sh_quote(suite),
+                None => r#""$(. /etc/os-release && echo "$VERSION_CODENAME")""#.to_string(),
+            };
+            let line = format!(
+                "{}\"$suite\"{}",
+                sh_quote(&format!("deb{signed_by} {} ", spec.url)),
+                sh_quote(&format!(" {}", spec.components))
+            );
+            script += &format!("suite={suite} && {}", write_file(&apt_list(name), &line));
+            script
+        }
+        PmKind::Dnf | PmKind::Yum => {
+            let mut repo = format!("[{name}]\nname={name}\nbaseurl={}\nenabled=1\n", spec.url);
+            let mut script = String::new();
+            match &spec.key {
+                Some(url) => {
+                    let key = rpm_key(name);
+                    repo += &format!("gpgcheck=1\ngpgkey=file://{key}");
+                    script += &format!(
+                        r#"if [ ! -s {path} ]; then {} && echo {CHANGED}; fi && {} && if ! rpm -qa 'gpg-pubkey*' --qf '%{{DESCRIPTION}}\n' | grep -qF -- "$line"; then $SUDO rpm --import {path} && echo {CHANGED}; fi && "#,
+                        download(url, &key),
+                        rpm_key_line(&key),
+                        path = sh_quote(&key)
+                    );
+                }
+                None => repo += "gpgcheck=0",
+            }
+            script += &format!("{{ {}; }}", write_file(&dnf_repo(name), &sh_quote(&repo)));
+            script
+        }
+        PmKind::Pacman | PmKind::Yay | PmKind::Paru => {
+            let mut script = String::new();
+            let sig_level = match &spec.key {
+                Some(key) if key.contains("://") => {
+                    bail!("pacman takes the fingerprint of a signing key, not {key}")
+                }
+                Some(key) => {
+                    let key = sh_quote(key);
+                    script += &format!(
+                        "if ! pacman-key --list-keys {key} >/dev/null 2>&1; then $SUDO pacman-key --recv-keys {key} && $SUDO pacman-key --lsign-key {key} && echo {CHANGED}; fi; "
+                    );
+                    "Required DatabaseOptional"
+                }
+                None => "Optional TrustAll",
+            };
+            // the fingerprint is kept for `remove` to delete the key
+            let fingerprint = match &spec.key {
+                Some(key) => format!("# key = {key}\n"),
+                None => String::new(),
+            };
+            let header = sh_quote(&format!("[{name}]"));
+            let section = sh_quote(&format!(
+                "[{name}]\n{fingerprint}SigLevel = {sig_level}\nServer = {}",
+                spec.url
+            ));
+            // a section that differs in any line is replaced
+            script += &format!(
+                r#"cur="$(awk -v s={header} '$0 == s {{ on = 1; print; next }} /^\[/ {{ on = 0 }} on && NF' {PACMAN_CONF})" && if [ "$cur" != {section} ]; then tmp="$(mktemp)" && {} > "$tmp" && printf '%s\n' {section} >> "$tmp" && $SUDO cp "$tmp" {PACMAN_CONF} && rm -f "$tmp" && echo {CHANGED}; fi"#,
+                pacman_without(&header)
+            );
+            script
+        }
+        _ => bail!("Repositories of {} aren't supported", kind.name()),
+    };
+    Ok(format!("{ROOT_PRELUDE}{script}"))
+}
+
+pub fn remove(kind: PmKind, name: &str) -> Result<String> {
+    check_name(name)?;
+    let script = match kind {
+        PmKind::Apt => remove_files(&[apt_list(name), apt_key(name)]),
+        PmKind::Dnf | PmKind::Yum => {
+            let key = rpm_key(name);
+            format!(
+                r#"if [ -s {path} ]; then {} && for k in $(rpm -qa 'gpg-pubkey*'); do if rpm -q "$k" --qf '%{{DESCRIPTION}}\n' | grep -qF -- "$line"; then $SUDO rpm -e "$k" && echo {CHANGED}; fi; done; fi; {}"#,
+                rpm_key_line(&key),
+                remove_files(&[dnf_repo(name), key.clone()]),
+                path = sh_quote(&key)
+            )
+        }
+        PmKind::Pacman | PmKind::Yay | PmKind::Paru => {
+            let header = sh_quote(&format!("[{name}]"));
+            format!(
+                r##"if grep -qxF {header} {PACMAN_CONF}; then key="$(awk -v s={header} '$0 == s {{ on = 1; next }} /^\[/ {{ on = 0 }} on && $1 == "#" && $2 == "key" {{ print $4 }}' {PACMAN_CONF})" && tmp="$(mktemp)" && {} > "$tmp" && $SUDO cp "$tmp" {PACMAN_CONF} && rm -f "$tmp" && echo {CHANGED} && if [ -n "$key" ]; then $SUDO pacman-key --delete "$key" >/dev/null; fi; fi"##,
+                pacman_without(&header)
+            )
+        }
+        _ => bail!("Repositories of {} aren't supported", kind.name()),
+    };
+    Ok(format!("{ROOT_PRELUDE}{script}"))
+}
+
+pub async fn apply(
+    ctx: &ContextWrapper,
+    device: &str,
+    what: String,
+    script: impl FnOnce(PmKind) -> Result<String>,
+    retry: &RetryOptions,
+) -> mlua::Result<bool> {
+    ctx.ctx()
+        .await
+        .interactor
+        .log(format!("{what} on {device}"))
+        .await;
+    let (uid, kind) = target(ctx, device, retry).await?;
+    let script = script(kind)?;
+    if ctx.dry_run {
+        return Ok(true);
+    }
+    // the repositories are root's whichever manager reads them
+    if !passes(ctx, &uid, ROOT_CHECK, retry).await? {
+        bail!(
+            "{uid} can't change the repositories as it is neither root nor allowed to sudo without a password"
+        );
+    }
+    let output = run(ctx, &uid, &script, retry).await?;
+    Ok(output.lines().any(|line| line == CHANGED))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PmKind, RepoSpec, add, check_name, remove};
+    use mlua::FromLua;
+
+    #[test]
+    fn repo_scripts() {
+        let lua = mlua::Lua::new();
+        let val = lua
+            .load("{name = 'docker', url = 'https://download.docker.com/linux/debian', key = 'https://download.docker.com/linux/debian/gpg', components = 'stable'}")
+            .eval::<mlua::Value>()
+            .expect("Failed to load");
+        let spec = RepoSpec::from_lua(val, &lua).expect("Failed to deserialize");
+        assert_eq!(spec.components, "stable");
+        let apt = add(PmKind::Apt, &spec).expect("Failed to build apt script");
+        assert!(
+            apt.contains(r#"curl -fsSL -o "$tmp" 'https://download.docker.com/linux/debian/gpg'"#)
+        );
+        assert!(apt.contains(
+            r#"'deb [signed-by=/etc/apt/keyrings/docker.asc] https://download.docker.com/linux/debian '"$suite"' stable'"#
+        ));
+        let dnf = add(PmKind::Dnf, &spec).expect("Failed to build dnf script");
+        assert!(dnf.contains("/etc/yum.repos.d/docker.repo"));
+        assert!(dnf.contains("if ! rpm -qa 'gpg-pubkey*'"));
+        assert!(add(PmKind::Pacman, &spec).is_err());
+        assert!(add(PmKind::Brew, &spec).is_err());
+        assert!(
+            remove(PmKind::Apt, "docker")
+                .expect("Failed to build apt script")
+                .contains("rm -f '/etc/apt/sources.list.d/docker.list'")
+        );
+        assert!(
+            remove(PmKind::Dnf, "docker")
+                .expect("Failed to build dnf script")
+                .contains("rm -f '/etc/pki/rpm-gpg/RPM-GPG-KEY-docker'")
+        );
+        assert!(check_name("../etc").is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context as _, Result, bail};
+use dv_api::process::ScriptExecutor;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+const PATH_PRELUDE: &str =
+    r#"p="$1"; case "$p" in "~") p="$HOME";; "~/"*) p="$HOME/${p#"~/"}";; esac; "#;
+
+#[derive(Debug, Clone)]
+pub enum Backend {
+    /// `docker exec`/`podman exec` into a running container
+    Container {
+        runtime: String,
+        container: String,
+        user: Option<String>,
+    },
+    /// another account of this machine, through `sudo -u`
+    Sudo { user: String },
+    /// a directory root, through `chroot` or `systemd-nspawn`; file operations run inside it as
+    /// well, so `..` or a symlink in the image can't lead them out of the root
+    Chroot { root: PathBuf, nspawn: bool },
+}
+
+impl Backend {
+    fn command(&self, args: &[&str], stdin: bool) -> Command {
+        let mut cmd = match self {
+            Backend::Container {
+                runtime,
+                container,
+                user,
+            } => {
+                let mut cmd = Command::new(runtime);
+                cmd.arg("exec");
+                if stdin {
+                    cmd.arg("-i");
+                }
+                if let Some(user) = user {
+                    cmd.args(["-u", user]);
+                }
+                cmd.arg(container).args(args);
+                cmd
+            }
+            Backend::Sudo { user } => {
+                let mut cmd = Command::new("sudo");
+                cmd.args(["-H", "-u", user, "--"]).args(args);
+                cmd
+            }
+            Backend::Chroot { root, nspawn: true } => {
+                let mut cmd = Command::new("systemd-nspawn");
+                cmd.args(["-q", "--pipe", "-D"])
+                    .arg(root)
+                    .arg("--")
+                    .args(args);
+                cmd
+            }
+            Backend::Chroot {
+                root,
+                nspawn: false,
+            } => {
+                let mut cmd = Command::new("chroot");
+                cmd.arg(root).args(args);
+                cmd
+            }
+        };
+        // a timed out attempt must not leave the process behind
+        cmd.kill_on_drop(true);
+        cmd
+    }
+}
+
+pub struct Output {
+    pub code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// A user that isn't managed by dv-api but reached by wrapping processes spawned on this machine.
+pub struct ProxyUser {
+    pub backend: Backend,
+    pub vars: HashMap<String, String>,
+}
+
+impl ProxyUser {
+    pub fn new(backend: Backend, vars: HashMap<String, String>) -> Self {
+        Self { backend, vars }
+    }
+    async fn run(&self, args: &[&str], stdin: Option<&[u8]>, reply: bool) -> Result<Output> {
+        let mut cmd = self.backend.command(args, stdin.is_some());
+        cmd.stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        });
+        if reply {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to spawn {:?}", self.backend))?;
+        if let Some(input) = stdin {
+            let mut pipe = child.stdin.take().expect("stdin is piped");
+            pipe.write_all(input).await?;
+            pipe.shutdown().await?;
+        }
+        let output = child.wait_with_output().await?;
+        Ok(Output {
+            code: output.status.code().unwrap_or(-1),
+            stdout: output.stdout,
+            stderr: output.stderr,
+        })
+    }
+    async fn run_checked(&self, args: &[&str], stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+        let output = self.run(args, stdin, true).await?;
+        if output.code != 0 {
+            bail!(
+                "{:?} exited with {}: {}",
+                self.backend,
+                output.code,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(output.stdout)
+    }
+    pub async fn probe(&self) -> Result<()> {
+        if let Backend::Chroot { root, .. } = &self.backend
+            && !tokio::fs::metadata(root).await?.is_dir()
+        {
+            bail!("{} is not a directory", root.display());
+        }
+        self.run_checked(&["true"], None).await.map(|_| ())
+    }
+    pub async fn exec(
+        &self,
+        commands: &str,
+        reply: bool,
+        etor: Option<ScriptExecutor>,
+    ) -> Result<Output> {
+        let shell = match etor {
+            Some(ScriptExecutor::Bash) => "bash",
+            _ => "sh",
+        };
+        self.run(&[shell, "-c", commands], None, reply).await
+    }
+    async fn read_bytes(&self, path: &str) -> Result<Vec<u8>> {
+        let script = format!("{PATH_PRELUDE}cat -- \"$p\"");
+        self.run_checked(&["sh", "-c", &script, "sh", path], None)
+            .await
+    }
+    pub async fn read(&self, path: &str) -> Result<String> {
+        Ok(String::from_utf8(self.read_bytes(path).await?)?)
+    }
+    async fn write_bytes(&self, path: &str, content: &[u8]) -> Result<()> {
+        let script = format!("{PATH_PRELUDE}mkdir -p -- \"$(dirname -- \"$p\")\" && cat > \"$p\"");
+        self.run_checked(&["sh", "-c", &script, "sh", path], Some(content))
+            .await
+            .map(|_| ())
+    }
+    pub async fn write(&self, path: &str, content: &str) -> Result<bool> {
+        self.write_bytes(path, content.as_bytes()).await?;
+        Ok(true)
+    }
+    async fn test(&self, test: &str, path: &str) -> Result<bool> {
+        let script = format!("{PATH_PRELUDE}[ {test} \"$p\" ]");
+        let output = self
+            .run(&["sh", "-c", &script, "sh", path], None, true)
+            .await?;
+        Ok(output.code == 0)
+    }
+    async fn is_dir(&self, path: &str) -> Result<bool> {
+        self.test("-d", path).await
+    }
+    pub async fn exists(&self, path: &str) -> Result<bool> {
+        self.test("-e", path).await
+    }
+    pub async fn remove(&self, path: &str) -> Result<()> {
+        let script = format!("{PATH_PRELUDE}rm -rf -- \"$p\"");
+        self.run_checked(&["sh", "-c", &script, "sh", path], None)
+            .await
+            .map(|_| ())
+    }
+    async fn mtime(&self, path: &str) -> Result<SystemTime> {
+        let script = format!("{PATH_PRELUDE}stat -c %Y -- \"$p\"");
+        let secs = self
+            .run_checked(&["sh", "-c", &script, "sh", path], None)
+            .await?;
+        let secs: u64 = String::from_utf8(secs)?.trim().parse()?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+    async fn set_mtime(&self, path: &str, mtime: SystemTime) -> Result<()> {
+        let secs = mtime.duration_since(SystemTime::UNIX_EPOCH)?.as_secs();
+        let script = format!("{PATH_PRELUDE}touch -m -d @{secs} -- \"$p\"");
+        self.run_checked(&["sh", "-c", &script, "sh", path], None)
+            .await
+            .map(|_| ())
+    }
+    async fn resolve(&self, path: &str) -> Result<String> {
+        if path != "~" && !path.starts_with("~/") {
+            return Ok(path.to_string());
+        }
+        let script = format!("{PATH_PRELUDE}printf %s \"$p\"");
+        let resolved = self
+            .run_checked(&["sh", "-c", &script, "sh", path], None)
+            .await?;
+        Ok(String::from_utf8(resolved)?)
+    }
+    /// copies a path of this machine to `dst` of the proxy user, streamed through its own
+    /// account so the copy is owned by it
+    pub async fn copy_in(&self, src: &str, dst: &str) -> Result<()> {
+        let dst = self.resolve(dst).await?;
+        let meta = tokio::fs::metadata(src).await?;
+        if !meta.is_dir() {
+            self.write_bytes(&dst, &tokio::fs::read(src).await?).await?;
+            // sync compares modification times
+            return self.set_mtime(&dst, meta.modified()?).await;
+        }
+        let archive = tar(&["-C", src, "-cf", "-", "."], None).await?;
+        let script = format!("{PATH_PRELUDE}mkdir -p -- \"$p\" && tar -C \"$p\" -xf -");
+        self.run_checked(&["sh", "-c", &script, "sh", &dst], Some(&archive))
+            .await
+            .map(|_| ())
+    }
+    pub async fn copy_out(&self, src: &str, dst: &str) -> Result<()> {
+        let src = self.resolve(src).await?;
+        match &self.backend {
+            Backend::Container {
+                runtime, container, ..
+            } => copy(runtime, &[&format!("{container}:{src}"), dst]).await,
+            _ => {
+                if !self.is_dir(&src).await? {
+                    let content = self.read_bytes(&src).await?;
+                    if let Some(parent) = Path::new(dst).parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::write(dst, content).await?;
+                    // sync compares modification times
+                    let mtime = self.mtime(&src).await?;
+                    std::fs::File::options()
+                        .write(true)
+                        .open(dst)?
+                        .set_modified(mtime)?;
+                    return Ok(());
+                }
+                let script = format!("{PATH_PRELUDE}tar -C \"$p\" -cf - .");
+                let archive = self
+                    .run_checked(&["sh", "-c", &script, "sh", &src], None)
+                    .await?;
+                tokio::fs::create_dir_all(dst).await?;
+                tar(&["-C", dst, "-xf", "-"], Some(&archive))
+                    .await
+                    .map(|_| ())
+            }
+        }
+    }
+}
+
+/// A private directory of this machine holding copies of proxy paths while they are synced,
+/// removed on drop.
+pub struct Staging(PathBuf);
+
+impl Staging {
+    pub fn new() -> Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "dv4lua-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut builder = std::fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder
+            .create(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self(dir))
+    }
+    pub fn path(&self, i: usize, side: usize) -> String {
+        self.0
+            .join(format!("{i}-{side}"))
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+async fn tar(args: &[&str], stdin: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = Command::new("tar")
+        .args(args)
+        .kill_on_drop(true)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn tar")?;
+    if let Some(input) = stdin {
+        let mut pipe = child.stdin.take().expect("stdin is piped");
+        pipe.write_all(input).await?;
+        pipe.shutdown().await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+async fn copy(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .arg("cp")
+        .args(args)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to spawn {program}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program} cp failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub fn expand_local(path: &str) -> String {
+    let home = directories::BaseDirs::new().map(|d| d.home_dir().to_path_buf());
+    match (path.strip_prefix('~'), home) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            format!("{}{}", home.display(), rest)
+        }
+        _ => path.to_string(),
+    }
+}
@@ -0,0 +1,120 @@
+use super::dev::*;
+use anyhow::anyhow;
+use humantime_serde::re::humantime::{format_duration, parse_duration};
+use mlua::{FromLua, LuaSerdeExt, Value};
+use std::{collections::HashMap, future::Future, time::Duration};
+
+/// backoff before the first retry when none is given, doubled on each further retry
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// `timeout`, `retries` and `backoff` of an operation, e.g. `{timeout = "30s", retries = 2}`.
+///
+/// Unset fields fall back to the defaults given on the command line.
+#[derive(serde::Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+pub struct RetryOptions {
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    pub retries: Option<u32>,
+    #[serde(default, with = "humantime_serde")]
+    pub backoff: Option<Duration>,
+}
+
+impl RetryOptions {
+    pub fn or(self, defaults: RetryOptions) -> Self {
+        Self {
+            timeout: self.timeout.or(defaults.timeout),
+            retries: self.retries.or(defaults.retries),
+            backoff: self.backoff.or(defaults.backoff),
+        }
+    }
+    /// takes the options out of a user's vars, where they apply to connecting
+    pub fn from_vars(vars: &mut HashMap<String, String>) -> Result<Self> {
+        let duration = |v: String| parse_duration(&v).map_err(|e| anyhow!("{v}: {e}"));
+        Ok(Self {
+            timeout: vars.remove("timeout").map(duration).transpose()?,
+            retries: vars.remove("retries").map(|v| v.parse()).transpose()?,
+            backoff: vars.remove("backoff").map(duration).transpose()?,
+        })
+    }
+}
+
+impl FromLua for RetryOptions {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        lua.from_value(value)
+    }
+}
+
+impl ContextWrapper {
+    pub(super) async fn retry<T, E, F, Fut>(
+        &self,
+        what: &str,
+        opts: &RetryOptions,
+        f: F,
+    ) -> Result<T, E>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: From<anyhow::Error> + std::fmt::Display,
+    {
+        let opts = opts.or(self.retry);
+        let retries = opts.retries.unwrap_or_default();
+        let mut backoff = opts.backoff.unwrap_or(DEFAULT_BACKOFF);
+        let mut attempt = 0;
+        loop {
+            let res = match opts.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, f())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(anyhow!("{what} timed out after {}", format_duration(timeout)).into())
+                    }),
+                None => f().await,
+            };
+            match res {
+                Err(e) if attempt < retries => {
+                    attempt += 1;
+                    self.ctx()
+                        .await
+                        .interactor
+                        .log(format!(
+                            "Retry {what} in {} ({attempt}/{retries}): {e}",
+                            format_duration(backoff)
+                        ))
+                        .await;
+                    tokio::time::sleep(backoff).await;
+                    backoff = backoff.saturating_mul(2);
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryOptions;
+    use mlua::FromLua;
+    use std::time::Duration;
+
+    #[test]
+    fn retry_options_serde() {
+        let lua = mlua::Lua::new();
+        let val = lua
+            .load("{timeout = '1m 30s', retries = 2}")
+            .eval::<mlua::Value>()
+            .expect("Failed to load");
+        let opts = RetryOptions::from_lua(val, &lua).expect("Failed to deserialize");
+        assert_eq!(opts.timeout, Some(Duration::from_secs(90)));
+        assert_eq!(opts.retries, Some(2));
+        assert_eq!(opts.backoff, None);
+
+        let defaults = RetryOptions {
+            timeout: Some(Duration::from_secs(10)),
+            retries: Some(0),
+            backoff: Some(Duration::from_secs(5)),
+        };
+        let opts = opts.or(defaults);
+        assert_eq!(opts.timeout, Some(Duration::from_secs(90)));
+        assert_eq!(opts.retries, Some(2));
+        assert_eq!(opts.backoff, Some(Duration::from_secs(5)));
+    }
+}
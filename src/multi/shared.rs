@@ -0,0 +1,195 @@
+use std::{
+    cell::{Cell, Ref, RefCell, RefMut},
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+use tokio::sync::Notify;
+
+/// A `RefCell` whose borrows may be held across `.await` by concurrent lua coroutines.
+///
+/// Shared borrows wait while an exclusive one is alive and an exclusive borrow waits until
+/// no borrow is alive, instead of panicking. Shared borrows are reentrant, so nested
+/// operations that each borrow the value can't deadlock each other.
+pub struct Shared<T> {
+    cell: RefCell<T>,
+    /// alive [`Lease`]s, exclusive borrows wait for them like for any shared borrow
+    leases: Cell<usize>,
+    released: Notify,
+}
+
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            cell: RefCell::new(value),
+            leases: Cell::new(0),
+            released: Notify::new(),
+        }
+    }
+    pub async fn borrow(&self) -> SharedRef<'_, T> {
+        loop {
+            // registered before trying so a release in between isn't missed
+            let released = self.released.notified();
+            if let Ok(inner) = self.cell.try_borrow() {
+                return SharedRef {
+                    inner: Some(inner),
+                    released: &self.released,
+                };
+            }
+            released.await;
+        }
+    }
+    pub async fn borrow_mut(&self) -> SharedRefMut<'_, T> {
+        loop {
+            let released = self.released.notified();
+            if self.leases.get() == 0
+                && let Ok(inner) = self.cell.try_borrow_mut()
+            {
+                return SharedRefMut {
+                    inner: Some(inner),
+                    released: &self.released,
+                };
+            }
+            released.await;
+        }
+    }
+    /// a shared borrow that isn't tied to `self`, for callers that need the value synchronously
+    pub async fn lease(self: &Rc<Self>) -> Lease<T> {
+        loop {
+            let released = self.released.notified();
+            if self.cell.try_borrow().is_ok() {
+                self.leases.set(self.leases.get() + 1);
+                return Lease(self.clone());
+            }
+            released.await;
+        }
+    }
+}
+
+pub struct SharedRef<'a, T> {
+    inner: Option<Ref<'a, T>>,
+    released: &'a Notify,
+}
+
+impl<T> Deref for SharedRef<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.inner.as_ref().expect("alive until dropped")
+    }
+}
+
+impl<T> Drop for SharedRef<'_, T> {
+    fn drop(&mut self) {
+        self.inner.take();
+        self.released.notify_waiters();
+    }
+}
+
+pub struct SharedRefMut<'a, T> {
+    inner: Option<RefMut<'a, T>>,
+    released: &'a Notify,
+}
+
+impl<T> Deref for SharedRefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.inner.as_ref().expect("alive until dropped")
+    }
+}
+
+impl<T> DerefMut for SharedRefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.inner.as_mut().expect("alive until dropped")
+    }
+}
+
+impl<T> Drop for SharedRefMut<'_, T> {
+    fn drop(&mut self) {
+        self.inner.take();
+        self.released.notify_waiters();
+    }
+}
+
+pub struct Lease<T>(Rc<Shared<T>>);
+
+impl<T> Lease<T> {
+    pub fn get(&self) -> Ref<'_, T> {
+        // no exclusive borrow is taken while a lease is alive
+        self.0.cell.borrow()
+    }
+}
+
+impl<T> Clone for Lease<T> {
+    fn clone(&self) -> Self {
+        self.0.leases.set(self.0.leases.get() + 1);
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Drop for Lease<T> {
+    fn drop(&mut self) {
+        self.0.leases.set(self.0.leases.get() - 1);
+        self.0.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shared;
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    #[tokio::test]
+    async fn shared_waits_instead_of_panicking() {
+        let shared = Rc::new(Shared::new(0));
+        let order = Rc::new(Cell::new(0));
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let reader = shared.borrow().await;
+                let writer = tokio::task::spawn_local({
+                    let (shared, order) = (shared.clone(), order.clone());
+                    async move {
+                        let mut value = shared.borrow_mut().await;
+                        *value += 1;
+                        order.set(order.get() + 1);
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                    }
+                });
+                tokio::task::yield_now().await;
+                // nested shared borrows are fine while a writer waits
+                assert_eq!(*shared.borrow().await, 0);
+                assert_eq!(order.get(), 0);
+                drop(reader);
+                tokio::task::yield_now().await;
+                assert_eq!(order.get(), 1);
+                // the reader waits for the writer to finish
+                assert_eq!(*shared.borrow().await, 1);
+                writer.await.expect("writer panicked");
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn shared_lease_holds_off_writers() {
+        let shared = Rc::new(Shared::new(0));
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let lease = shared.lease().await;
+                let writer = tokio::task::spawn_local({
+                    let shared = shared.clone();
+                    async move { *shared.borrow_mut().await += 1 }
+                });
+                tokio::task::yield_now().await;
+                assert_eq!(*lease.get(), 0);
+                let clone = lease.clone();
+                drop(lease);
+                tokio::task::yield_now().await;
+                assert_eq!(*clone.get(), 0);
+                drop(clone);
+                writer.await.expect("writer panicked");
+                assert_eq!(*shared.lease().await.get(), 1);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,62 @@
+use super::dev::*;
+use anyhow::anyhow;
+use futures::future::join_all;
+use mlua::{AnyUserData, Function, Lua, MultiValue, Table, Value};
+use std::{cell::RefCell, rc::Rc};
+use tokio::task::JoinHandle;
+
+#[derive(Clone)]
+pub struct Task {
+    handle: Rc<RefCell<Option<JoinHandle<mlua::Result<MultiValue>>>>>,
+}
+
+impl Task {
+    pub fn spawn(f: Function, args: MultiValue) -> Self {
+        let handle = tokio::task::spawn_local(f.call_async::<MultiValue>(args));
+        Self {
+            handle: Rc::new(RefCell::new(Some(handle))),
+        }
+    }
+    pub fn is_pending(&self) -> bool {
+        self.handle.borrow().is_some()
+    }
+    pub async fn join(&self) -> mlua::Result<MultiValue> {
+        let handle = self.handle.borrow_mut().take();
+        let Some(handle) = handle else {
+            return Err(mlua::Error::external(anyhow!("Task already joined")));
+        };
+        handle.await.map_err(mlua::Error::external)?
+    }
+}
+
+impl UserData for Task {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("is_finished", |_, this, ()| {
+            Ok(this
+                .handle
+                .borrow()
+                .as_ref()
+                .is_none_or(JoinHandle::is_finished))
+        });
+        methods.add_async_method("join", |_, this, ()| async move { this.join().await });
+    }
+}
+
+/// joins every task, raising the first error once all of them are done;
+/// each result is the first value returned by its function
+pub async fn join(lua: &Lua, tasks: Vec<Task>) -> mlua::Result<Table> {
+    let results = join_all(tasks.iter().map(Task::join)).await;
+    let table = lua.create_table()?;
+    for (i, res) in results.into_iter().enumerate() {
+        let first = res?.into_iter().next().unwrap_or(Value::Nil);
+        table.raw_set(i + 1, first)?;
+    }
+    Ok(table)
+}
+
+pub fn tasks_from_table(tasks: Table) -> mlua::Result<Vec<Task>> {
+    tasks
+        .sequence_values::<AnyUserData>()
+        .map(|ud| Ok(ud?.borrow::<Task>()?.clone()))
+        .collect()
+}
@@ -1,10 +1,19 @@
 use super::dev::*;
+use super::facts::Facts;
+use super::group::{Group, GroupOptions};
+use super::proxy::{Backend, ProxyUser, expand_local};
+use super::retry::RetryOptions;
+use crate::inventory::{UserKind, UserSpec};
+use crate::ssh_config::{SshConfig, glob};
+use crate::util::conversion_error;
 use dv_api::process::ScriptExecutor;
 use dv_wrap::User;
-use dv_wrap::ops;
 use mlua::{FromLua, LuaSerdeExt, Table, Value};
+use std::{collections::HashMap, rc::Rc};
 use tracing::debug;
 
+const VARS_NS: &str = "vars";
+
 pub struct UserWrapper {
     ctx: ContextWrapper,
     uid: String,
@@ -14,12 +23,20 @@ impl UserWrapper {
     pub fn new(ctx: ContextWrapper, uid: String) -> Self {
         Self { ctx, uid }
     }
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
+    async fn var(&self, key: &str) -> Option<String> {
+        self.ctx.user_var(&self.uid, key).await
+    }
 }
 
 #[derive(serde::Deserialize, Default)]
-struct ExecOptions {
-    reply: bool,
-    etor: Option<ScriptExecutor>,
+pub struct ExecOptions {
+    pub reply: bool,
+    pub etor: Option<ScriptExecutor>,
+    #[serde(flatten)]
+    pub retry: RetryOptions,
 }
 
 impl FromLua for ExecOptions {
@@ -27,7 +44,7 @@ impl FromLua for ExecOptions {
         if let Some(b) = value.as_boolean() {
             return Ok(ExecOptions {
                 reply: b,
-                etor: None,
+                ..Default::default()
             });
         }
         lua.from_value(value)
@@ -39,8 +56,10 @@ impl UserData for UserWrapper {
             "exec",
             |_, this, (commands, opt): (String, Option<ExecOptions>)| async move {
                 let opt = opt.unwrap_or_default();
-                let ctx = this.ctx.ctx();
-                ctx.interactor
+                this.ctx
+                    .ctx()
+                    .await
+                    .interactor
                     .log(format!(
                         "Exec on {}: {} (reply: {}, etor: {:?})",
                         this.uid, commands, opt.reply, opt.etor
@@ -53,7 +72,10 @@ impl UserData for UserWrapper {
                         this.ctx.lua().create_string("")?,
                     ));
                 }
-                let output = ops::exec(&ctx, &this.uid, &commands, opt.reply, opt.etor).await?;
+                let output = this
+                    .ctx
+                    .exec(&this.uid, &commands, opt.reply, opt.etor, &opt.retry)
+                    .await?;
                 Ok((
                     output.code,
                     this.ctx.lua().create_string(output.stdout)?,
@@ -63,32 +85,107 @@ impl UserData for UserWrapper {
         );
         methods.add_async_method(
             "write",
-            |_, this, (path, content): (String, String)| async move {
-                let ctx = this.ctx.ctx();
-                ctx.interactor
+            |_, this, (path, content, retry): (String, String, Option<RetryOptions>)| async move {
+                this.ctx
+                    .ctx()
+                    .await
+                    .interactor
                     .log(format!("Write on {}: {}", this.uid, path))
                     .await;
                 if this.ctx.dry_run {
                     return Ok(true);
                 }
-                Ok(ops::write(&ctx, &this.uid, &path, &content).await?)
+                let retry = retry.unwrap_or_default();
+                Ok(this.ctx.write(&this.uid, &path, &content, &retry).await?)
+            },
+        );
+        methods.add_async_method(
+            "read",
+            |_, this, (path, retry): (String, Option<RetryOptions>)| async move {
+                this.ctx
+                    .ctx()
+                    .await
+                    .interactor
+                    .log(format!("Read on {}: {}", this.uid, path))
+                    .await;
+                let retry = retry.unwrap_or_default();
+                Ok(this.ctx.read(&this.uid, &path, &retry).await?)
             },
         );
-        methods.add_async_method("read", |_, this, path: String| async move {
-            let ctx = this.ctx.ctx();
-            ctx.interactor
-                .log(format!("Read on {}: {}", this.uid, path))
-                .await;
-            Ok(ops::read(&ctx, &this.uid, &path).await?)
+        methods.add_async_method("facts", |lua, this, refresh: Option<bool>| async move {
+            let facts = Facts::get(&this.ctx, &this.uid, refresh.unwrap_or_default()).await?;
+            lua.to_value_with(
+                &facts,
+                mlua::SerializeOptions::new().serialize_none_to_null(false),
+            )
         });
-        methods.add_meta_method(mlua::MetaMethod::Index, |_, this, key: String| {
-            let ctx = this.ctx.ctx();
-            let user = ctx.get_user(&this.uid).expect("User must exist");
-            if let Some(value) = user.vars.get(&key) {
-                return Ok(Some(value.clone()));
-            }
-            Ok(None)
+        methods.add_async_method(
+            "persist",
+            |_, this, keys: mlua::Variadic<String>| async move {
+                let ctx = this.ctx.ctx().await;
+                ctx.interactor
+                    .log(format!("Persist on {}: {}", this.uid, keys.join(", ")))
+                    .await;
+                if this.ctx.dry_run {
+                    return Ok(());
+                }
+                let mut persisted: HashMap<String, String> = this
+                    .ctx
+                    .state()
+                    .get(VARS_NS, &this.uid)?
+                    .unwrap_or_default();
+                for key in keys.iter() {
+                    match this.var(key).await {
+                        Some(value) => persisted.insert(key.clone(), value),
+                        None => persisted.remove(key),
+                    };
+                }
+                let mut state = this.ctx.state_mut();
+                if persisted.is_empty() {
+                    state.remove(VARS_NS, &this.uid)?;
+                } else {
+                    state.set(VARS_NS, &this.uid, &persisted)?;
+                }
+                Ok(())
+            },
+        );
+        methods.add_async_meta_method(mlua::MetaMethod::Index, |_, this, key: String| async move {
+            Ok(this.var(&key).await)
         });
+        methods.add_async_meta_method(
+            mlua::MetaMethod::NewIndex,
+            |_, this, (key, value): (String, Value)| async move {
+                let value = match value {
+                    Value::Nil => None,
+                    Value::Boolean(b) => Some(b.to_string()),
+                    Value::Integer(i) => Some(i.to_string()),
+                    Value::Number(n) => Some(n.to_string()),
+                    Value::String(s) => Some(s.to_str()?.to_string()),
+                    _ => Err(conversion_error(
+                        value.type_name(),
+                        "user var",
+                        Some("expected string, number, boolean or nil"),
+                    ))?,
+                };
+                {
+                    let mut vars = this.ctx.vars.borrow_mut();
+                    let vars = vars.entry(this.uid.clone()).or_default();
+                    match &value {
+                        Some(value) => vars.insert(key.clone(), value.clone()),
+                        None => vars.remove(&key),
+                    };
+                }
+                // templates and dot read the vars of the dv-wrap user
+                let mut ctx = this.ctx.ctx_mut().await;
+                if let Some(user) = ctx.get_user_mut(&this.uid) {
+                    match value {
+                        Some(value) => user.vars.insert(key, value),
+                        None => user.vars.remove(&key),
+                    };
+                }
+                Ok(())
+            },
+        );
     }
 }
 
@@ -115,60 +212,268 @@ impl std::ops::DerefMut for UserManager {
     }
 }
 
-impl UserData for UserManager {
-    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
-        fn add_user_prepare(obj: Table) -> mlua::Result<dv_api::multi::Config> {
+fn spec_from_table(kind: UserKind, obj: Table) -> mlua::Result<UserSpec> {
+    let mut spec = UserSpec {
+        kind,
+        ..Default::default()
+    };
+    for v in obj.pairs::<String, Value>() {
+        let (name, value) = v?;
+        let value = match value {
+            Value::Boolean(b) if name == "is_system" => {
+                spec.is_system = Some(b);
+                continue;
+            }
+            Value::Boolean(b) => b.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.to_str()?.to_string(),
+            _ => continue,
+        };
+        spec.vars.insert(name, value);
+    }
+    Ok(spec)
+}
+
+fn proxy_backend(kind: UserKind, vars: &mut HashMap<String, String>) -> mlua::Result<Backend> {
+    fn required(
+        vars: &mut HashMap<String, String>,
+        kind: UserKind,
+        key: &str,
+    ) -> mlua::Result<String> {
+        vars.remove(key).ok_or_else(|| {
+            conversion_error(
+                "table",
+                format!("{kind:?}"),
+                Some(format!("{key} required")),
+            )
+        })
+    }
+    let backend = match kind {
+        UserKind::Container => {
+            let container = required(vars, kind, "container")?;
+            let runtime = vars
+                .remove("runtime")
+                .unwrap_or_else(|| "docker".to_string());
+            if runtime != "docker" && runtime != "podman" {
+                return Err(conversion_error(
+                    "table",
+                    "Container",
+                    Some(format!(
+                        "unsupported runtime {runtime}, expected docker or podman"
+                    )),
+                ));
+            }
+            Backend::Container {
+                runtime,
+                container,
+                user: vars.remove("user"),
+            }
+        }
+        UserKind::LocalAs => Backend::Sudo {
+            user: required(vars, kind, "user")?,
+        },
+        UserKind::Chroot => Backend::Chroot {
+            root: expand_local(&required(vars, kind, "root")?).into(),
+            nspawn: vars.remove("nspawn").is_some_and(|v| v == "true"),
+        },
+        UserKind::Cur | UserKind::Ssh => unreachable!("not a proxy user"),
+    };
+    vars.entry("os".to_string())
+        .or_insert_with(|| "linux".to_string());
+    Ok(backend)
+}
+
+async fn add_user(ctx: &ContextWrapper, uid: String, spec: UserSpec) -> mlua::Result<bool> {
+    if ctx.contains_user(&uid).await {
+        return Ok(false);
+    }
+    let mut vars: HashMap<String, String> = ctx.state().get(VARS_NS, &uid)?.unwrap_or_default();
+    vars.extend(spec.vars);
+    if matches!(spec.kind, UserKind::Cur | UserKind::Ssh) {
+        let retry = RetryOptions::from_vars(&mut vars)?;
+        let config = || {
             let mut cfg = dv_api::multi::Config::default();
-            for v in obj.pairs::<String, Value>() {
-                let (name, value) = v?;
-                if name == "is_system" && value.is_boolean() {
-                    cfg.is_system = value.as_boolean();
-                    continue;
-                }
-                let Some(value) = value.as_string() else {
-                    continue;
-                };
-                cfg.set(name, value.to_str()?.to_string());
+            cfg.is_system = spec.is_system;
+            for (name, value) in &vars {
+                cfg.set(name, value);
             }
-            Ok(cfg)
+            cfg
+        };
+        let user = if spec.kind == UserKind::Cur {
+            let mut cfg = config();
+            cfg.set("hid", "local");
+            User::local(cfg).await?
+        } else {
+            let has_host = vars.contains_key("host");
+            ctx.retry(&format!("connect to {uid}"), &retry, || {
+                let mut cfg = config();
+                if !has_host {
+                    cfg.set("host", &uid);
+                }
+                User::ssh(cfg)
+            })
+            .await?
+        };
+        let mut ctx = ctx.ctx_mut().await;
+        if ctx.contains_user(&uid) {
+            return Ok(false);
         }
+        return Ok(ctx.add_user(uid, user).await.map(|_| true)?);
+    }
+    let backend = proxy_backend(spec.kind, &mut vars)?;
+    let user = ProxyUser::new(backend, vars);
+    user.probe().await?;
+    if ctx.contains_user(&uid).await {
+        return Ok(false);
+    }
+    ctx.proxies.borrow_mut().insert(uid, Rc::new(user));
+    Ok(true)
+}
 
-        methods.add_async_method_mut(
-            "add_cur",
-            async move |_, this, obj: Table| -> mlua::Result<bool> {
-                let mut cfg = add_user_prepare(obj)?;
-                let mut ctx = this.ctx_mut();
-                if ctx.contains_user("cur") {
-                    return Ok(false);
+pub async fn is_user(ctx: &ContextWrapper, uid: &str) -> bool {
+    ctx.contains_user(uid).await
+        || ctx.declared.borrow().contains_key(uid)
+        || ctx.inventory.user(uid).is_some()
+}
+
+/// checks that the device `hid` exists, adding the users declared on it on first access
+pub async fn ensure_device(ctx: &ContextWrapper, hid: &str) -> mlua::Result<bool> {
+    if ctx.ctx().await.devices.contains_key(hid) {
+        return Ok(true);
+    }
+    let mut uids: Vec<String> = ctx
+        .declared
+        .borrow()
+        .iter()
+        .filter(|(_, spec)| spec.vars.get("hid").is_some_and(|h| h == hid))
+        .map(|(uid, _)| uid.clone())
+        .collect();
+    uids.extend(ctx.inventory.users_of(hid));
+    if uids.is_empty() {
+        return Ok(false);
+    }
+    for uid in uids {
+        ensure_user(ctx, &uid).await?;
+    }
+    Ok(ctx.ctx().await.devices.contains_key(hid))
+}
+
+pub async fn ensure_user(ctx: &ContextWrapper, uid: &str) -> mlua::Result<bool> {
+    if ctx.contains_user(uid).await {
+        return Ok(true);
+    }
+    let declared = ctx.declared.borrow_mut().remove(uid);
+    let Some(spec) = declared.or_else(|| ctx.inventory.user(uid)) else {
+        return Ok(false);
+    };
+    add_user(ctx, uid.to_string(), spec).await?;
+    Ok(true)
+}
+
+impl UserData for UserManager {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "import_ssh_config",
+            |_, this, (path, pattern): (Option<String>, Option<String>)| async move {
+                let home = directories::BaseDirs::new()
+                    .map(|d| d.home_dir().to_path_buf())
+                    .ok_or_else(|| mlua::Error::external("Failed to locate home directory"))?;
+                let path = match path {
+                    Some(path) => expand_local(&path).into(),
+                    None => home.join(".ssh").join("config"),
+                };
+                let config = SshConfig::load(&path, &home)?;
+                let mut imported = Vec::new();
+                for alias in config.aliases() {
+                    if pattern.as_deref().is_some_and(|p| !glob(p, &alias))
+                        || this.contains_user(&alias).await
+                    {
+                        continue;
+                    }
+                    let host = config.resolve(&alias);
+                    let mut spec = UserSpec::default();
+                    spec.vars.insert("hid".to_string(), alias.clone());
+                    spec.vars.insert(
+                        "host".to_string(),
+                        host.host_name.unwrap_or_else(|| alias.clone()),
+                    );
+                    for (key, value) in [
+                        ("user", host.user),
+                        ("port", host.port),
+                        (
+                            "identity_file",
+                            host.identity_file.map(|f| expand_local(&f)),
+                        ),
+                        ("proxy_jump", host.proxy_jump),
+                    ] {
+                        if let Some(value) = value {
+                            spec.vars.insert(key.to_string(), value);
+                        }
+                    }
+                    this.declared.borrow_mut().insert(alias.clone(), spec);
+                    imported.push(alias);
                 }
-                cfg.set("hid", "local");
-                Ok(ctx
-                    .add_user("cur".to_string(), User::local(cfg).await?)
-                    .await
-                    .map(|_| true)?)
+                Ok(imported)
             },
         );
-        methods.add_async_method_mut(
-            "add_ssh",
-            async move |_, this, (uid, obj): (String, Table)| -> mlua::Result<bool> {
-                let mut cfg = add_user_prepare(obj)?;
-                let mut ctx = this.ctx_mut();
-                if ctx.contains_user(&uid) {
-                    return Ok(false);
-                }
-                cfg.set("host", &uid);
-                Ok(ctx
-                    .add_user(uid, User::ssh(cfg).await?)
-                    .await
-                    .map(|_| true)?)
+        methods.add_method(
+            "add_group",
+            |_, this, (name, members): (String, Vec<String>)| {
+                this.groups.borrow_mut().insert(name, members);
+                Ok(())
+            },
+        );
+        methods.add_async_method(
+            "group",
+            |lua, this, (name, opts): (String, Option<Value>)| async move {
+                let opts: GroupOptions = opts
+                    .map(|opts| lua.from_value(opts))
+                    .transpose()?
+                    .unwrap_or_default();
+                let members = this.groups.borrow().get(&name).cloned();
+                let Some(members) =
+                    members.or_else(|| this.inventory.group(&name).map(<[_]>::to_vec))
+                else {
+                    return Err(conversion_error(
+                        "string",
+                        "Group",
+                        Some(format!("group {name} not found")),
+                    ));
+                };
+                Group::new(this.ctx.clone(), &name, members, opts).await
             },
         );
+        methods.add_async_method(
+            "add_cur",
+            async move |_, this, obj: Table| -> mlua::Result<bool> {
+                add_user(
+                    &this,
+                    "cur".to_string(),
+                    spec_from_table(UserKind::Cur, obj)?,
+                )
+                .await
+            },
+        );
+        for (name, kind) in [
+            ("add_ssh", UserKind::Ssh),
+            ("add_container", UserKind::Container),
+            ("add_local_as", UserKind::LocalAs),
+            ("add_chroot", UserKind::Chroot),
+        ] {
+            methods.add_async_method(
+                name,
+                move |_, this, (uid, obj): (String, Table)| async move {
+                    add_user(&this, uid, spec_from_table(kind, obj)?).await
+                },
+            );
+        }
 
-        methods.add_meta_method(
+        methods.add_async_meta_method(
             mlua::MetaMethod::Index,
-            |_, this, key: String| -> mlua::Result<Option<UserWrapper>> {
+            async move |_, this, key: String| -> mlua::Result<Option<UserWrapper>> {
                 debug!("Accessing user: {}", key);
-                if !this.ctx().contains_user(&key) {
+                if !ensure_user(&this, &key).await? {
                     return Ok(None);
                 }
                 Ok(Some(UserWrapper::new(this.ctx.clone(), key)))
@@ -180,6 +485,7 @@ impl UserData for UserManager {
 #[cfg(test)]
 mod tests {
     use super::ExecOptions;
+    use crate::{inventory::Inventory, state::State};
     use dv_api::process::ScriptExecutor;
     use mlua::FromLua;
 
@@ -206,4 +512,56 @@ mod tests {
         assert!(!opt.reply);
         assert_eq!(opt.etor, Some(ScriptExecutor::Bash));
     }
+
+    #[tokio::test]
+    async fn user_manager_adds_concurrently() {
+        let dir = std::env::temp_dir().join(format!("dv4lua-um-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create dir");
+        // a root that isn't a directory fails the probe, after it awaited its metadata
+        let root = dir.join("not-a-dir");
+        std::fs::write(&root, "").expect("Failed to write root");
+        let ctx = dv_wrap::Context::new(
+            dv_wrap::MultiDB::default(),
+            Some(dir.clone()),
+            dv_wrap::TermInteractor::new().expect("Failed to create interactor"),
+        );
+        let state = State::open(&dir.join("db.sqlite")).expect("Failed to open state");
+        let ctx = crate::multi::register(
+            ctx,
+            state,
+            Inventory::default(),
+            Default::default(),
+            1,
+            false,
+        )
+        .expect("Failed to register");
+        ctx.lua()
+            .globals()
+            .set("ROOT", root.to_string_lossy().to_string())
+            .expect("Failed to set root");
+        let errors: Vec<String> = tokio::task::LocalSet::new()
+            .run_until(
+                ctx.lua()
+                    .load(
+                        r#"
+                        local um = dv:um()
+                        local function add(uid)
+                            return function()
+                                local ok, err = pcall(um.add_chroot, um, uid, { root = ROOT })
+                                return tostring(err)
+                            end
+                        end
+                        return dv:all({ add("a"), add("b") })
+                        "#,
+                    )
+                    .eval_async(),
+            )
+            .await
+            .expect("Failed to run");
+        assert_eq!(errors.len(), 2);
+        for err in errors {
+            assert!(err.contains("is not a directory"), "{err}");
+        }
+        std::fs::remove_dir_all(&dir).ok();
+    }
 }
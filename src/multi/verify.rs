@@ -0,0 +1,175 @@
+use super::proxy::expand_local;
+use super::{RetryOptions, dev::*};
+use anyhow::{Context as _, bail};
+use mlua::{FromLua, LuaSerdeExt, Value};
+use sha2::{Digest, Sha256, Sha512};
+
+/// What a download must match, checked on every call so a tampered cache is caught too.
+#[derive(serde::Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct DlOptions {
+    pub sha256: Option<String>,
+    pub sha512: Option<String>,
+    pub minisign: Option<String>,
+    pub gpg: Option<String>,
+    pub signature: Option<String>,
+    #[serde(flatten)]
+    pub retry: RetryOptions,
+}
+
+impl FromLua for DlOptions {
+    fn from_lua(value: Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        lua.from_value(value)
+    }
+}
+
+impl DlOptions {
+    pub fn signature_url(&self, url: &str) -> Result<Option<String>> {
+        // a signature file is either a minisign or a gpg one
+        if self.minisign.is_some() && self.gpg.is_some() {
+            bail!("Only one of minisign and gpg can verify {url}");
+        }
+        match (&self.signature, &self.minisign, &self.gpg) {
+            (Some(sig), _, _) => Ok(Some(sig.clone())),
+            (None, Some(_), _) => Ok(Some(format!("{url}.minisig"))),
+            (None, None, Some(_)) => bail!("gpg verification of {url} needs a signature url"),
+            (None, None, None) => Ok(None),
+        }
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn check_digests(data: &[u8], opts: &DlOptions) -> Result<()> {
+    let checks = [
+        ("sha256", &opts.sha256, hex(&Sha256::digest(data))),
+        ("sha512", &opts.sha512, hex(&Sha512::digest(data))),
+    ];
+    for (name, expected, actual) in checks {
+        if let Some(expected) = expected
+            && !expected.trim().eq_ignore_ascii_case(&actual)
+        {
+            bail!(
+                "{name} mismatch: expected {}, got {actual}",
+                expected.trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// checks the file at `path` against `opts`, with its detached signature at `sig_path`,
+/// discarding both on a mismatch so a later call downloads them again
+pub async fn verify(path: &str, sig_path: Option<&str>, opts: &DlOptions) -> Result<()> {
+    let path = expand_local(path);
+    let sig_path = sig_path.map(expand_local);
+    let res = check(&path, sig_path.as_deref(), opts).await;
+    if res.is_err() {
+        for path in std::iter::once(&path).chain(&sig_path) {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+    res
+}
+
+async fn check(path: &str, sig_path: Option<&str>, opts: &DlOptions) -> Result<()> {
+    if opts.sha256.is_some() || opts.sha512.is_some() {
+        check_digests(&tokio::fs::read(path).await?, opts)?;
+    }
+    if let Some(sig_path) = sig_path {
+        check_signature(path, sig_path, opts).await?;
+    }
+    Ok(())
+}
+
+async fn check_signature(path: &str, sig_path: &str, opts: &DlOptions) -> Result<()> {
+    if let Some(key) = &opts.minisign {
+        let key = key.trim();
+        let pk = if key.contains('\n') {
+            minisign_verify::PublicKey::decode(key)
+        } else {
+            minisign_verify::PublicKey::from_base64(key)
+        }
+        .context("Invalid minisign public key")?;
+        let sig = tokio::fs::read_to_string(sig_path).await?;
+        let sig = minisign_verify::Signature::decode(&sig).context("Invalid minisign signature")?;
+        let data = tokio::fs::read(path).await?;
+        pk.verify(&data, &sig, false)
+            .context("minisign signature mismatch")?;
+    }
+    if let Some(keyring) = &opts.gpg {
+        let output = tokio::process::Command::new("gpgv")
+            .arg("--keyring")
+            .arg(expand_local(keyring))
+            .arg(sig_path)
+            .arg(path)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run gpgv")?;
+        if !output.status.success() {
+            bail!(
+                "gpg signature mismatch: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DlOptions, check_digests, verify};
+
+    #[test]
+    fn dl_digests() {
+        let opts = DlOptions {
+            sha256: Some(
+                "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD".to_string(),
+            ),
+            ..Default::default()
+        };
+        check_digests(b"abc", &opts).expect("Digest should match");
+        assert!(check_digests(b"abd", &opts).is_err());
+        let opts = DlOptions {
+            minisign: Some("RWQ...".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            opts.signature_url("https://example.com/a.tar.gz")
+                .expect("Failed to get signature url"),
+            Some("https://example.com/a.tar.gz.minisig".to_string())
+        );
+        let opts = DlOptions {
+            gpg: Some("~/.gnupg/trusted.gpg".to_string()),
+            ..opts
+        };
+        assert!(opts.signature_url("https://example.com/a.tar.gz").is_err());
+    }
+
+    #[tokio::test]
+    async fn dl_discard_on_mismatch() {
+        let dir = std::env::temp_dir().join(format!("dv4lua-verify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Failed to create dir");
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "abd").expect("Failed to write file");
+        let path = path.to_string_lossy();
+        let opts = DlOptions {
+            sha256: Some(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_string(),
+            ),
+            ..Default::default()
+        };
+        assert!(verify(&path, None, &opts).await.is_err());
+        assert!(!std::path::Path::new(&*path).exists());
+
+        std::fs::write(&*path, "abc").expect("Failed to write file");
+        verify(&path, None, &opts)
+            .await
+            .expect("Digest should match");
+        assert!(std::path::Path::new(&*path).exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
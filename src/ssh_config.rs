@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+#[derive(Debug, Default)]
+struct Block {
+    patterns: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+/// A concrete host resolved from the config, options are first-match-wins like ssh itself.
+#[derive(Debug, Default, PartialEq)]
+pub struct SshHost {
+    pub alias: String,
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<String>,
+    pub identity_file: Option<String>,
+    pub proxy_jump: Option<String>,
+}
+
+pub fn glob(pattern: &str, s: &str) -> bool {
+    fn inner(p: &[u8], s: &[u8]) -> bool {
+        match (p.first(), s.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => inner(&p[1..], s) || (!s.is_empty() && inner(p, &s[1..])),
+            (Some(b'?'), Some(_)) => inner(&p[1..], &s[1..]),
+            (Some(a), Some(b)) if a == b => inner(&p[1..], &s[1..]),
+            _ => false,
+        }
+    }
+    inner(pattern.as_bytes(), s.as_bytes())
+}
+
+fn split_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (key, value) = line
+        .split_once(|c: char| c.is_whitespace() || c == '=')
+        .unwrap_or((line, ""));
+    let value = value
+        .trim_start_matches(|c: char| c.is_whitespace() || c == '=')
+        .trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((key.to_lowercase(), value.to_string()))
+}
+
+fn expand_home(path: &str, home: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => home.join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+impl SshConfig {
+    pub fn load(path: &Path, home: &Path) -> Result<Self> {
+        let mut config = SshConfig::default();
+        config.load_into(path, home, &mut Block::default(), 0)?;
+        Ok(config)
+    }
+    fn load_into(&mut self, path: &Path, home: &Path, cur: &mut Block, depth: usize) -> Result<()> {
+        let content = std::fs::read_to_string(path)?;
+        // relative includes are resolved against ~/.ssh, like ssh does for the user config
+        let base = home.join(".ssh");
+        for line in content.lines() {
+            let Some((key, value)) = split_line(line) else {
+                continue;
+            };
+            match key.as_str() {
+                "host" => {
+                    let block = std::mem::replace(
+                        cur,
+                        Block {
+                            patterns: value.split_whitespace().map(str::to_string).collect(),
+                            options: Vec::new(),
+                        },
+                    );
+                    self.push(block);
+                }
+                // `Match` blocks depend on runtime state, skip them until the next `Host`
+                "match" => {
+                    let block = std::mem::take(cur);
+                    self.push(block);
+                    cur.patterns.push("!*".to_string());
+                }
+                "include" if depth < 16 => {
+                    let patterns = cur.patterns.clone();
+                    for pattern in value.split_whitespace() {
+                        let pattern = expand_home(pattern, home);
+                        let pattern = if pattern.is_absolute() {
+                            pattern
+                        } else {
+                            base.join(pattern)
+                        };
+                        for include in Self::expand_include(&pattern)? {
+                            self.load_into(&include, home, cur, depth + 1)?;
+                        }
+                    }
+                    // a `Host` in the included file ends there, the lines after the `Include`
+                    // are still under the one it appeared in
+                    let block = std::mem::replace(
+                        cur,
+                        Block {
+                            patterns,
+                            options: Vec::new(),
+                        },
+                    );
+                    self.push(block);
+                }
+                _ => cur.options.push((key, value)),
+            }
+        }
+        if depth == 0 {
+            let block = std::mem::take(cur);
+            self.push(block);
+        }
+        Ok(())
+    }
+    fn push(&mut self, block: Block) {
+        if !block.options.is_empty() {
+            self.blocks.push(block);
+        }
+    }
+    fn expand_include(pattern: &Path) -> Result<Vec<PathBuf>> {
+        let name = pattern
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !name.contains(['*', '?']) {
+            return Ok(if pattern.exists() {
+                vec![pattern.to_path_buf()]
+            } else {
+                Vec::new()
+            });
+        }
+        let Some(dir) = pattern.parent().filter(|d| d.is_dir()) else {
+            return Ok(Vec::new());
+        };
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if glob(&name, &entry.file_name().to_string_lossy()) {
+                files.push(entry.path());
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+    fn matches(block: &Block, alias: &str) -> bool {
+        // options before the first `Host` line apply to every host
+        if block.patterns.is_empty() {
+            return true;
+        }
+        let mut matched = false;
+        for pattern in &block.patterns {
+            if let Some(negated) = pattern.strip_prefix('!') {
+                if glob(negated, alias) {
+                    return false;
+                }
+            } else if glob(pattern, alias) {
+                matched = true;
+            }
+        }
+        matched
+    }
+    /// concrete aliases declared in `Host` lines, patterns are only used as defaults
+    pub fn aliases(&self) -> Vec<String> {
+        let mut aliases = Vec::new();
+        for block in &self.blocks {
+            for pattern in &block.patterns {
+                if pattern.contains(['*', '?', '!']) || aliases.contains(pattern) {
+                    continue;
+                }
+                aliases.push(pattern.clone());
+            }
+        }
+        aliases
+    }
+    pub fn resolve(&self, alias: &str) -> SshHost {
+        let mut options: HashMap<&str, &str> = HashMap::new();
+        for block in self.blocks.iter().filter(|b| Self::matches(b, alias)) {
+            for (key, value) in &block.options {
+                options.entry(key.as_str()).or_insert(value.as_str());
+            }
+        }
+        let get = |key: &str| options.get(key).map(|v| v.to_string());
+        SshHost {
+            alias: alias.to_string(),
+            host_name: get("hostname").map(|h| h.replace("%h", alias)),
+            user: get("user"),
+            port: get("port"),
+            identity_file: get("identityfile"),
+            proxy_jump: get("proxyjump"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SshConfig, SshHost, glob};
+
+    #[test]
+    fn ssh_config_resolve() {
+        let dir = std::env::temp_dir().join(format!("dv4lua-ssh-config-{}", std::process::id()));
+        let ssh = dir.join(".ssh");
+        std::fs::create_dir_all(ssh.join("conf.d")).expect("Failed to create dirs");
+        std::fs::write(
+            ssh.join("config"),
+            "Include conf.d/*.conf\n\
+             Host rt rt-r\n  HostName 10.0.0.2\n  User km0e\n\
+             Host jump\n  HostName jump.example.com\n\
+             Match exec \"true\"\n  User nobody\n\
+             Host *\n  Port 22\n  IdentityFile ~/.ssh/id_ed25519\n",
+        )
+        .expect("Failed to write config");
+        std::fs::write(
+            ssh.join("conf.d/work.conf"),
+            "Host work\n  HostName=%h.corp.example.com\n  ProxyJump jump\n  Port 2222\n",
+        )
+        .expect("Failed to write include");
+
+        let config = SshConfig::load(&ssh.join("config"), &dir).expect("Failed to load");
+        assert_eq!(config.aliases(), vec!["work", "rt", "rt-r", "jump"]);
+        assert_eq!(
+            config.resolve("work"),
+            SshHost {
+                alias: "work".to_string(),
+                host_name: Some("work.corp.example.com".to_string()),
+                user: None,
+                port: Some("2222".to_string()),
+                identity_file: Some("~/.ssh/id_ed25519".to_string()),
+                proxy_jump: Some("jump".to_string()),
+            }
+        );
+        let rt = config.resolve("rt-r");
+        assert_eq!(rt.host_name.as_deref(), Some("10.0.0.2"));
+        assert_eq!(rt.user.as_deref(), Some("km0e"));
+        assert_eq!(rt.port.as_deref(), Some("22"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ssh_config_global_options() {
+        let dir = std::env::temp_dir().join(format!("dv4lua-ssh-global-{}", std::process::id()));
+        let ssh = dir.join(".ssh");
+        std::fs::create_dir_all(&ssh).expect("Failed to create dirs");
+        std::fs::write(
+            ssh.join("config"),
+            "User admin\nPort 2200\n\
+             Host rt\n  HostName 10.0.0.2\n  Port 22\n",
+        )
+        .expect("Failed to write config");
+
+        let config = SshConfig::load(&ssh.join("config"), &dir).expect("Failed to load");
+        assert_eq!(config.aliases(), vec!["rt"]);
+        let rt = config.resolve("rt");
+        assert_eq!(rt.user.as_deref(), Some("admin"));
+        // the leading block comes first, so it wins like in ssh
+        assert_eq!(rt.port.as_deref(), Some("2200"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ssh_config_include_in_host() {
+        let dir = std::env::temp_dir().join(format!("dv4lua-ssh-include-{}", std::process::id()));
+        let ssh = dir.join(".ssh");
+        std::fs::create_dir_all(&ssh).expect("Failed to create dirs");
+        std::fs::write(
+            ssh.join("config"),
+            "Host rt\n  Include extra.conf\n  User km0e\n\
+             Host jump\n  HostName jump.example.com\n",
+        )
+        .expect("Failed to write config");
+        std::fs::write(
+            ssh.join("extra.conf"),
+            "Port 2222\nHost work\n  HostName work.example.com\n",
+        )
+        .expect("Failed to write include");
+
+        let config = SshConfig::load(&ssh.join("config"), &dir).expect("Failed to load");
+        assert_eq!(config.aliases(), vec!["rt", "work", "jump"]);
+        let rt = config.resolve("rt");
+        assert_eq!(rt.port.as_deref(), Some("2222"));
+        assert_eq!(rt.user.as_deref(), Some("km0e"));
+        assert_eq!(config.resolve("work").user, None);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn ssh_config_glob() {
+        assert!(glob("*", "anything"));
+        assert!(glob("rt-?", "rt-r"));
+        assert!(glob("*.example.com", "a.example.com"));
+        assert!(!glob("*.example.com", "example.com"));
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Serialize, de::DeserializeOwned};
+
+/// Key-value store for data outside the versioned cache entries, in its own table of the cache db.
+pub struct State {
+    conn: Connection,
+}
+
+impl State {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dv4lua_state (
+                ns TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value TEXT NOT NULL,
+                PRIMARY KEY (ns, key)
+            )",
+            [],
+        )?;
+        Ok(Self { conn })
+    }
+    pub fn get<T: DeserializeOwned>(&self, ns: &str, key: &str) -> Result<Option<T>> {
+        let value: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT value FROM dv4lua_state WHERE ns = ?1 AND key = ?2",
+                params![ns, key],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(value.map(|v| serde_json::from_str(&v)).transpose()?)
+    }
+    pub fn set<T: Serialize>(&mut self, ns: &str, key: &str, value: &T) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO dv4lua_state (ns, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (ns, key) DO UPDATE SET value = excluded.value",
+            params![ns, key, serde_json::to_string(value)?],
+        )?;
+        Ok(())
+    }
+    pub fn remove(&mut self, ns: &str, key: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM dv4lua_state WHERE ns = ?1 AND key = ?2",
+            params![ns, key],
+        )?;
+        Ok(())
+    }
+}
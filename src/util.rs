@@ -1,8 +1,35 @@
 use anyhow::bail;
 use dv_wrap::ops::SyncOpt;
-use mlua::Error;
+use mlua::{Error, FromLua, Value};
+
+const SYNC_OPT_NAMES: [(&str, SyncOpt); 6] = [
+    ("overwrite", SyncOpt::OVERWRITE),
+    ("update", SyncOpt::UPDATE),
+    ("upload", SyncOpt::UPLOAD),
+    ("download", SyncOpt::DOWNLOAD),
+    ("delete_src", SyncOpt::DELETESRC),
+    ("delete_dst", SyncOpt::DELETEDST),
+];
+
+fn sync_opt(name: &str) -> dv_wrap::Result<SyncOpt> {
+    match SYNC_OPT_NAMES.iter().find(|(n, _)| *n == name) {
+        Some((_, opt)) => Ok(*opt),
+        None => bail!(
+            "Invalid confirm option: {}, expected one of {}",
+            name,
+            SYNC_OPT_NAMES.map(|(n, _)| n).join(", ")
+        ),
+    }
+}
 
 pub fn sync_opts(s: &str) -> dv_wrap::Result<Vec<SyncOpt>> {
+    if !s.chars().all(|c| c.is_ascii_digit()) {
+        return s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(sync_opt)
+            .collect();
+    }
     let mut opts = Vec::new();
     for c in s.chars() {
         if !c.is_ascii_digit() || c == '0' {
@@ -16,6 +43,57 @@ pub fn sync_opts(s: &str) -> dv_wrap::Result<Vec<SyncOpt>> {
     Ok(opts)
 }
 
+/// Confirm options from lua, a string for [`sync_opts`], a list of names
+/// or a table of flags as in `{overwrite = true, delete_dst = true}`.
+#[derive(Default)]
+pub struct Confirm(pub Vec<SyncOpt>);
+
+impl FromLua for Confirm {
+    fn from_lua(value: Value, _: &mlua::Lua) -> mlua::Result<Self> {
+        match value {
+            Value::Nil => Ok(Confirm::default()),
+            Value::String(s) => Ok(Confirm(sync_opts(&s.to_str()?)?)),
+            Value::Table(t) => {
+                let mut opts = Vec::new();
+                for pair in t.pairs::<Value, Value>() {
+                    match pair? {
+                        (Value::Integer(_), Value::String(name)) => {
+                            opts.push(sync_opt(&name.to_str()?)?)
+                        }
+                        (Value::String(name), Value::Boolean(set)) => {
+                            let opt = sync_opt(&name.to_str()?)?;
+                            if set {
+                                opts.push(opt);
+                            }
+                        }
+                        (key, value) => {
+                            return Err(conversion_error(
+                                "table",
+                                "Confirm",
+                                Some(format!(
+                                    "unexpected entry of {} = {}, expected option names or flags",
+                                    key.type_name(),
+                                    value.type_name()
+                                )),
+                            ));
+                        }
+                    }
+                }
+                Ok(Confirm(opts))
+            }
+            _ => Err(conversion_error(
+                value.type_name(),
+                "Confirm",
+                Some("expected a string or a table of options"),
+            )),
+        }
+    }
+}
+
+pub fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
+
 /// constructs a FromLuaConversionError
 pub fn conversion_error(
     from: &'static str,
@@ -28,3 +106,25 @@ pub fn conversion_error(
         message: message.map(|m| m.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::sync_opts;
+    use dv_wrap::ops::SyncOpt;
+
+    #[test]
+    fn sync_opts_names() {
+        assert_eq!(
+            sync_opts("upload, update").expect("Failed to parse"),
+            vec![SyncOpt::UPLOAD, SyncOpt::UPDATE]
+        );
+        assert_eq!(
+            sync_opts("delete_dst").expect("Failed to parse"),
+            vec![SyncOpt::DELETEDST]
+        );
+        assert_eq!(sync_opts("1").expect("Failed to parse").len(), 1);
+        assert!(sync_opts("").expect("Failed to parse").is_empty());
+        let err = sync_opts("yu").expect_err("Invalid name accepted");
+        assert!(err.to_string().contains("delete_src"));
+    }
+}